@@ -12,7 +12,51 @@ pub const ENV_ADMIN_PORT: &str = "ADMIN_PORT";
 pub const ENV_DATABASE_URL: &str = "DATABASE_URL";
 pub const ENV_ACCESS_LOG: &str = "ACCESS_LOG";
 pub const ENV_CONFIG_FILE_PATH: &str = "CONFIG_FILE_PATH";
+pub const ENV_ZONE: &str = "ZONE";
+pub const ENV_REGION: &str = "REGION";
 pub const DEFAULT_HTTP_TIMEOUT: u64 = 10;
 pub const DEFAULT_TEMPORARY_DIR: &str = "temporary";
 pub const GRPC_STATUS_HEADER: &str = "grpc-status";
-pub const GRPC_STATUS_OK: &str = "0";
\ No newline at end of file
+pub const GRPC_STATUS_OK: &str = "0";
+pub const DEFAULT_PEAK_EWMA_DECAY_MILLIS: u64 = 10_000;
+pub const DEFAULT_PEAK_EWMA_RTT_MILLIS: u64 = 30;
+pub const DEFAULT_HASH_RING_VIRTUAL_NODES: usize = 160;
+pub const MAX_REGEX_CACHE_SIZE: usize = 1024;
+pub const DEFAULT_AFFINITY_COOKIE_NAME: &str = "monoio_gateway_affinity";
+pub const DEFAULT_AFFINITY_COOKIE_PATH: &str = "/";
+pub const DEFAULT_SLOW_START_MIN_WEIGHT_PERCENT: u64 = 10;
+pub const SLOW_START_WEIGHT_SCALE: u64 = 100;
+pub const SLOW_START_FAILURE_THRESHOLD: u64 = 3;
+pub const DEFAULT_DISCOVERY_REFRESH_SECONDS: u64 = 30;
+pub const DEFAULT_DNS_PORT: u16 = 53;
+pub const DEFAULT_DNS_TIMEOUT_MILLIS: u64 = 2000;
+pub const DEFAULT_HTTP_POLL_TIMEOUT_MILLIS: u64 = 5000;
+pub const DEFAULT_RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+pub const DEFAULT_POOL_IDLE_TIMEOUT_SECONDS: u64 = 60;
+pub const DEFAULT_POOL_SWEEP_INTERVAL_SECONDS: u64 = 5;
+pub const DEFAULT_HTTP2_MAX_CONCURRENT_STREAMS: usize = 100;
+pub const DEFAULT_MIRROR_TIMEOUT_MILLIS: u64 = 10_000;
+pub const SHADOW_HOST_SUFFIX: &str = "-shadow";
+pub const DEFAULT_CLUSTER_NAME: &str = "default";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const DEFAULT_PROXY_PROTOCOL_TIMEOUT_MILLIS: u64 = 5000;
+pub const MAX_PROXY_PROTOCOL_HEADER_SIZE: usize = 4096;
+pub const MAX_REQUEST_HEADER_COUNT: usize = 96;
+pub const DEFAULT_REDIRECT_STATUS: u16 = 301;
+pub const DEFAULT_INDEX_FILE: &str = "index.html";
+pub const DEFAULT_TRY_FILES: &str = "$uri $uri/";
+pub const STATIC_FILE_CHUNK_SIZE: usize = 64 * 1024;
+pub const STATIC_FILE_MAX_PENDING_CHUNKS: usize = 4;
+pub const BLOCKING_THREAD_COUNT: usize = 4;
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+pub const COMPRESSION_CHUNK_SIZE: usize = 16 * 1024;
+pub const BROTLI_QUALITY: u32 = 4;
+pub const BROTLI_WINDOW_SIZE: u32 = 22;
+pub const ZSTD_LEVEL: i32 = 3;
+pub const MAX_DECOMPRESSED_REQUEST_SIZE: usize = 64 * 1024 * 1024;
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
+pub const DEFAULT_CACHE_MAX_SIZE: usize = 64 * 1024 * 1024;
+pub const DEFAULT_CACHE_MAX_ENTRY_SIZE: usize = 1024 * 1024;
+pub const MAX_CACHE_PURGE_LOG_SIZE: usize = 1024;
+pub const ACCESS_LOG_TARGET: &str = "access_log";
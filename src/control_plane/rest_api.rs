@@ -1,4 +1,5 @@
 use crate::constants::common_constants::DEFAULT_TEMPORARY_DIR;
+use crate::discovery::discovery_service::start_discovery_task;
 
 use crate::proxy::http_proxy::create_monoio_runtime;
 use crate::vojo::app_config::ApiService;
@@ -6,10 +7,14 @@ use crate::vojo::app_config::Route;
 
 use crate::vojo::app_config::AppConfig;
 use crate::vojo::app_error::AppError;
+use crate::vojo::discovery_provider::DiscoveryProvider;
 
 use crate::ensure;
 use crate::vojo::base_response::BaseResponse;
+use crate::vojo::cache_config::CachePurge;
 use crate::vojo::handler::Handler;
+use crate::vojo::route_table::get_route_conflicts;
+use crate::vojo::route_table::RouteTable;
 use axum::extract::State;
 use axum::response::IntoResponse;
 use axum::routing::delete;
@@ -17,6 +22,7 @@ use axum::routing::{get, post, put};
 use axum::Router;
 use futures::channel::mpsc::UnboundedSender as Sender;
 use futures::SinkExt;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::env;
 use std::net::SocketAddr;
@@ -62,12 +68,19 @@ async fn post_app_config_with_error(
     handler: Handler,
 ) -> Result<impl axum::response::IntoResponse, AppError> {
     let port = api_service.listen_port;
-    let app_config = handler
+    RouteTable::new(&api_service.service_config.routes)?;
+    if let Some(proxy_protocol) = &api_service.service_config.proxy_protocol {
+        proxy_protocol.check()?;
+    }
+    let mut app_config = handler
         .shared_app_config
-        .read()
-        .map_err(|e| AppError(e.to_string()))?
-        .clone();
-    if !app_config.api_service_config.contains_key(&port) {
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    if let std::collections::hash_map::Entry::Vacant(entry) =
+        app_config.api_service_config.entry(port)
+    {
+        entry.insert(api_service);
+        drop(app_config);
         create_monoio_runtime(port, handler);
         let data = BaseResponse {
             response_code: 0,
@@ -107,6 +120,7 @@ async fn put_route_with_error(
     port: i32,
     route_id: String,
 ) -> Result<String, AppError> {
+    RouteTable::new(std::slice::from_ref(&_route_vistor))?;
     let mut app_config = handler
         .shared_app_config
         .write()
@@ -120,23 +134,283 @@ async fn put_route_with_error(
             .iter_mut()
             .find(|s| s.route_id == route_id);
         if let Some(route) = value {
+            route.priority = _route_vistor.priority;
             route.matcher = _route_vistor.matcher;
             route.host_name = _route_vistor.host_name;
+            route.rewrite_headers = _route_vistor.rewrite_headers;
+            route.request_headers = _route_vistor.request_headers;
+            route.response_headers = _route_vistor.response_headers;
+            route.forwarding_headers = _route_vistor.forwarding_headers;
+            route.host_rewrite = _route_vistor.host_rewrite;
+            route.auto_host_rewrite = _route_vistor.auto_host_rewrite;
+            route.compression = _route_vistor.compression;
+            route.cache = _route_vistor.cache;
+            route.cors = _route_vistor.cors;
+            route.size_limits = _route_vistor.size_limits;
+            route.action = _route_vistor.action;
             route.authentication = _route_vistor.authentication;
             route.ratelimit = _route_vistor.ratelimit;
             route.allow_deny_list = _route_vistor.allow_deny_list;
+            route.session_affinity = _route_vistor.session_affinity;
+            route.slow_start_config = _route_vistor.slow_start_config;
+            route.discovery_provider = _route_vistor.discovery_provider;
+            route.connection_pool = _route_vistor.connection_pool;
+            route.upstream_protocol = _route_vistor.upstream_protocol;
+            route.http2_config = _route_vistor.http2_config;
+            route.mirror = match (route.mirror.take(), _route_vistor.mirror) {
+                (Some(mut mirror), Some(new_mirror)) => {
+                    mirror.percentage = new_mirror.percentage;
+                    mirror.connection_pool = new_mirror.connection_pool;
+                    mirror.upstream_protocol = new_mirror.upstream_protocol;
+                    mirror.http2_config = new_mirror.http2_config;
+                    mirror.size_limits = new_mirror.size_limits;
+                    mirror
+                        .route_cluster
+                        .update_cluster(new_mirror.route_cluster)?;
+                    Some(mirror)
+                }
+                (_, new_mirror) => new_mirror,
+            };
+            route.traffic_split = match (route.traffic_split.take(), _route_vistor.traffic_split) {
+                (Some(mut traffic_split), Some(new_traffic_split)) => {
+                    traffic_split.update_traffic_split(new_traffic_split)?;
+                    Some(traffic_split)
+                }
+                (_, new_traffic_split) => new_traffic_split,
+            };
+            route
+                .route_cluster
+                .update_cluster(_route_vistor.route_cluster)?;
         } else {
             return Err(AppError(format!("The route {} is not found", route_id)));
         }
+        api_service.service_config.on_routes_changed();
+        let data = BaseResponse {
+            response_code: 0,
+            response_object: get_route_conflicts(&api_service.service_config.routes, &route_id),
+        };
+        Ok(serde_json::to_string(&data).unwrap())
     } else {
-        return Err(AppError(format!("The port {} is not in use", port)));
+        Err(AppError(format!("The port {} is not in use", port)))
+    }
+}
+async fn post_route(
+    State(state): State<Handler>,
+    axum::extract::Path(port): axum::extract::Path<i32>,
+    axum::extract::Json(route): axum::extract::Json<Route>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match post_route_with_error(route, state, port).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
+}
+// The response contains the routes which shadow or overlap the new route.
+async fn post_route_with_error(
+    route: Route,
+    handler: Handler,
+    port: i32,
+) -> Result<String, AppError> {
+    RouteTable::new(std::slice::from_ref(&route))?;
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    let service_config = &mut app_config
+        .api_service_config
+        .get_mut(&port)
+        .ok_or(AppError(format!("The port {} is not in use", port)))?
+        .service_config;
+    ensure!(
+        service_config
+            .routes
+            .iter()
+            .all(|item| item.route_id != route.route_id),
+        format!("The route {} already exists", route.route_id)
+    );
+    let route_id = route.route_id.clone();
+    route.start_warm_up();
+    service_config.routes.push(route);
+    service_config.on_routes_changed();
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: get_route_conflicts(&service_config.routes, &route_id),
+    };
+    Ok(serde_json::to_string(&data).unwrap())
+}
+async fn get_pool_stats(
+    State(state): State<Handler>,
+    axum::extract::Path((port, route_id)): axum::extract::Path<(i32, String)>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match get_pool_stats_with_error(state, port, route_id).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+async fn get_pool_stats_with_error(
+    handler: Handler,
+    port: i32,
+    route_id: String,
+) -> Result<String, AppError> {
+    let app_config = handler
+        .shared_app_config
+        .read()
+        .map_err(|e| AppError(e.to_string()))?;
+    let route = app_config
+        .api_service_config
+        .get(&port)
+        .ok_or(AppError(format!("The port {} is not in use", port)))?
+        .service_config
+        .routes
+        .iter()
+        .find(|item| item.route_id == route_id)
+        .ok_or(AppError(format!("The route {} is not found", route_id)))?;
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: route.pool_stats.get_snapshot(),
+    };
+    serde_json::to_string(&data).map_err(|e| AppError(e.to_string()))
+}
+async fn put_traffic_split_weights(
+    State(state): State<Handler>,
+    axum::extract::Path((port, route_id)): axum::extract::Path<(i32, String)>,
+    axum::extract::Json(weights): axum::extract::Json<HashMap<String, u32>>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match put_traffic_split_weights_with_error(weights, state, port, route_id).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+// Only the weights are changed, the rest of the route is kept.
+async fn put_traffic_split_weights_with_error(
+    weights: HashMap<String, u32>,
+    handler: Handler,
+    port: i32,
+    route_id: String,
+) -> Result<String, AppError> {
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    let route = app_config
+        .api_service_config
+        .get_mut(&port)
+        .ok_or(AppError(format!("The port {} is not in use", port)))?
+        .service_config
+        .routes
+        .iter_mut()
+        .find(|item| item.route_id == route_id)
+        .ok_or(AppError(format!("The route {} is not found", route_id)))?;
+    route
+        .traffic_split
+        .as_mut()
+        .ok_or(AppError(format!(
+            "The route {} has no traffic split",
+            route_id
+        )))?
+        .update_weights(weights)?;
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: 0,
+    };
+    Ok(serde_json::to_string(&data).unwrap())
+}
+async fn put_discovery_provider(
+    State(state): State<Handler>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    axum::extract::Json(discovery_provider): axum::extract::Json<Box<dyn DiscoveryProvider>>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match put_discovery_provider_with_error(discovery_provider, state, name).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+async fn put_discovery_provider_with_error(
+    discovery_provider: Box<dyn DiscoveryProvider>,
+    handler: Handler,
+    name: String,
+) -> Result<String, AppError> {
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    app_config
+        .discovery_providers
+        .insert(name, discovery_provider);
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: 0,
+    };
+    Ok(serde_json::to_string(&data).unwrap())
+}
+async fn delete_discovery_provider(
+    State(state): State<Handler>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match delete_discovery_provider_with_error(state, name).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+async fn delete_discovery_provider_with_error(
+    handler: Handler,
+    name: String,
+) -> Result<String, AppError> {
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    let is_referred = app_config.api_service_config.values().any(|api_service| {
+        api_service
+            .service_config
+            .routes
+            .iter()
+            .any(|route| route.discovery_provider.as_deref() == Some(name.as_str()))
+    });
+    if is_referred {
+        return Err(AppError(format!(
+            "The discovery provider {} is still referred by the routes",
+            name
+        )));
+    }
+    app_config
+        .discovery_providers
+        .remove(&name)
+        .ok_or(AppError(format!(
+            "The discovery provider {} is not found",
+            name
+        )))?;
     let data = BaseResponse {
         response_code: 0,
         response_object: 0,
     };
     Ok(serde_json::to_string(&data).unwrap())
 }
+async fn post_cache_purge(
+    State(state): State<Handler>,
+    axum::extract::Json(cache_purge): axum::extract::Json<CachePurge>,
+) -> Result<impl axum::response::IntoResponse, Infallible> {
+    match post_cache_purge_with_error(cache_purge, state).await {
+        Ok(r) => Ok((axum::http::StatusCode::OK, r)),
+        Err(e) => Ok((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+// The workers remove the entries on their next cache lookup, the response contains the
+// generation of the purge.
+async fn post_cache_purge_with_error(
+    cache_purge: CachePurge,
+    handler: Handler,
+) -> Result<String, AppError> {
+    ensure!(
+        cache_purge.prefix.starts_with('/'),
+        format!("The prefix {} must start with /", cache_purge.prefix)
+    );
+    let generation = handler.cache_purge_log.push(cache_purge)?;
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: generation,
+    };
+    Ok(serde_json::to_string(&data).unwrap())
+}
 async fn save_config_to_file(data: AppConfig) -> Result<(), AppError> {
     let result: bool = Path::new(DEFAULT_TEMPORARY_DIR).is_dir();
     if !result {
@@ -162,13 +436,25 @@ async fn save_config_to_file(data: AppConfig) -> Result<(), AppError> {
 pub fn get_router(handler: Handler) -> Router {
     axum::Router::new()
         .route("/appConfig", get(get_app_config).post(post_app_config))
+        .route("/route/:id", post(post_route))
         .route("/route/:id/:route_id", put(put_route))
+        .route("/route/:id/:route_id/poolStats", get(get_pool_stats))
+        .route(
+            "/route/:id/:route_id/trafficSplit/weights",
+            put(put_traffic_split_weights),
+        )
+        .route("/cache/purge", post(post_cache_purge))
+        .route(
+            "/discoveryProvider/:name",
+            put(put_discovery_provider).delete(delete_discovery_provider),
+        )
         // .route("/route/:port", put(put_route))
         .with_state(handler)
         .layer(TraceLayer::new_for_http())
         .layer(CorsLayer::permissive())
 }
 pub async fn start_control_plane(handler: Handler, port: i32) -> Result<(), AppError> {
+    tokio::spawn(start_discovery_task(handler.clone()));
     let app = get_router(handler);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port as u16));
@@ -0,0 +1,207 @@
+use crate::discovery::file_watcher::is_same_file;
+use crate::discovery::file_watcher::FileWatcher;
+use crate::vojo::app_error::AppError;
+use crate::vojo::discovery_provider::DiscoveryProvider;
+use crate::vojo::handler::Handler;
+use crate::vojo::route::DiscoveredEndpoint;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Default)]
+struct DiscoveryStatus {
+    // The provider is refreshed at once after it has been replaced.
+    provider_debug: String,
+    refresh_time: Option<Instant>,
+    endpoints: Vec<DiscoveredEndpoint>,
+}
+struct DiscoveryTarget {
+    name: String,
+    discovery_provider: Box<dyn DiscoveryProvider>,
+    routes: Vec<DiscoveryRoute>,
+}
+struct DiscoveryRoute {
+    port: i32,
+    route_id: String,
+    current_endpoints: Vec<String>,
+}
+// Refresh the endpoints of every discovery provider which is referred by the routes. The
+// endpoints are resolved without the config lock, a failed or empty refresh keeps the last
+// endpoints.
+pub async fn start_discovery_task(handler: Handler) {
+    let mut discovery_statuses: HashMap<String, DiscoveryStatus> = HashMap::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut file_watcher_option = match FileWatcher::new() {
+        Ok(file_watcher) => Some(file_watcher),
+        Err(e) => {
+            error!("Create the file watcher error,the error is {}", e);
+            None
+        }
+    };
+    loop {
+        let changed_paths = match &file_watcher_option {
+            Some(file_watcher) => tokio::select! {
+                _ = interval.tick() => vec![],
+                changed_paths_result = file_watcher.changed_paths() => {
+                    changed_paths_result.unwrap_or_else(|e| {
+                        error!("Watch the files error,the error is {}", e);
+                        vec![]
+                    })
+                }
+            },
+            None => {
+                interval.tick().await;
+                vec![]
+            }
+        };
+        let targets = match get_discovery_targets(&handler) {
+            Ok(targets) => targets,
+            Err(e) => {
+                error!("Get the discovery providers error,the error is {}", e);
+                continue;
+            }
+        };
+        discovery_statuses.retain(|name, _| targets.iter().any(|item| item.name == *name));
+        for target in targets {
+            let watch_path_option = target.discovery_provider.get_watch_path();
+            if let (Some(file_watcher), Some(watch_path)) =
+                (file_watcher_option.as_mut(), watch_path_option.as_ref())
+            {
+                if let Err(e) = file_watcher.watch(watch_path) {
+                    error!("{}", e);
+                }
+            }
+            let is_changed = watch_path_option.is_some_and(|watch_path| {
+                changed_paths
+                    .iter()
+                    .any(|changed_path| is_same_file(&watch_path, changed_path))
+            });
+            let discovery_status = discovery_statuses.entry(target.name.clone()).or_default();
+            let provider_debug = target.discovery_provider.get_debug();
+            if discovery_status.provider_debug != provider_debug {
+                discovery_status.provider_debug = provider_debug;
+                discovery_status.refresh_time = None;
+            }
+            let is_due = discovery_status.refresh_time.is_none_or(|refresh_time| {
+                refresh_time.elapsed() >= target.discovery_provider.get_refresh_interval()
+            });
+            let mut is_updated = false;
+            if is_changed || is_due {
+                discovery_status.refresh_time = Some(Instant::now());
+                match target.discovery_provider.get_endpoints().await {
+                    Ok(mut endpoints) if !endpoints.is_empty() => {
+                        endpoints.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+                        endpoints.dedup_by(|a, b| a.endpoint == b.endpoint);
+                        is_updated = discovery_status.endpoints != endpoints;
+                        discovery_status.endpoints = endpoints;
+                    }
+                    Ok(_) => error!(
+                        "Can not discover any endpoint of the provider {},keep the last endpoints",
+                        target.name
+                    ),
+                    Err(e) => error!(
+                        "Discover the endpoints of the provider {} error,the error is {}",
+                        target.name, e
+                    ),
+                }
+            }
+            if discovery_status.endpoints.is_empty() {
+                continue;
+            }
+            let discovered_endpoints: Vec<String> = discovery_status
+                .endpoints
+                .iter()
+                .map(|item| item.endpoint.clone())
+                .collect();
+            if is_updated {
+                info!(
+                    "The endpoints of the provider {} have changed to {:?}",
+                    target.name, discovered_endpoints
+                );
+            }
+            // The routes which refer to the provider later are updated with the last endpoints.
+            for route in target.routes.iter() {
+                if !is_updated && route.current_endpoints == discovered_endpoints {
+                    continue;
+                }
+                if let Err(e) = update_endpoints(
+                    &handler,
+                    route.port,
+                    &route.route_id,
+                    discovery_status.endpoints.clone(),
+                ) {
+                    error!(
+                        "Update the endpoints of the route {} error,the error is {}",
+                        route.route_id, e
+                    );
+                }
+            }
+        }
+    }
+}
+fn get_discovery_targets(handler: &Handler) -> Result<Vec<DiscoveryTarget>, AppError> {
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    let mut targets: Vec<DiscoveryTarget> = app_config
+        .discovery_providers
+        .iter()
+        .map(|(name, discovery_provider)| DiscoveryTarget {
+            name: name.clone(),
+            discovery_provider: discovery_provider.clone(),
+            routes: vec![],
+        })
+        .collect();
+    for (port, api_service) in app_config.api_service_config.iter_mut() {
+        for route in api_service.service_config.routes.iter_mut() {
+            let Some(name) = route.discovery_provider.clone() else {
+                continue;
+            };
+            let Some(target) = targets.iter_mut().find(|item| item.name == name) else {
+                error!(
+                    "The discovery provider {} of the route {} is not found",
+                    name, route.route_id
+                );
+                continue;
+            };
+            let mut current_endpoints: Vec<String> = route
+                .route_cluster
+                .get_all_route()?
+                .into_iter()
+                .map(|item| item.endpoint.clone())
+                .collect();
+            current_endpoints.sort();
+            target.routes.push(DiscoveryRoute {
+                port: *port,
+                route_id: route.route_id.clone(),
+                current_endpoints,
+            });
+        }
+    }
+    targets.retain(|item| !item.routes.is_empty());
+    Ok(targets)
+}
+pub fn update_endpoints(
+    handler: &Handler,
+    port: i32,
+    route_id: &str,
+    endpoints: Vec<DiscoveredEndpoint>,
+) -> Result<(), AppError> {
+    let mut app_config = handler
+        .shared_app_config
+        .write()
+        .map_err(|e| AppError(e.to_string()))?;
+    let route = app_config
+        .api_service_config
+        .get_mut(&port)
+        .and_then(|api_service| {
+            api_service
+                .service_config
+                .routes
+                .iter_mut()
+                .find(|item| item.route_id == route_id)
+        })
+        .ok_or(AppError(format!("The route {} is not found", route_id)))?;
+    route.route_cluster.update_endpoints(endpoints)
+}
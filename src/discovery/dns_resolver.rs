@@ -0,0 +1,455 @@
+use crate::constants::common_constants::DEFAULT_DNS_PORT;
+use crate::constants::common_constants::DEFAULT_DNS_TIMEOUT_MILLIS;
+use crate::constants::common_constants::DEFAULT_RESOLV_CONF_PATH;
+use crate::vojo::app_error::AppError;
+use rand::Rng;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+pub const RECORD_TYPE_A: u16 = 1;
+pub const RECORD_TYPE_AAAA: u16 = 28;
+pub const RECORD_TYPE_SRV: u16 = 33;
+const RECORD_CLASS_IN: u16 = 1;
+const MAX_DNS_PACKET_SIZE: usize = 4096;
+const TRUNCATED_FLAG: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    Address(String, IpAddr),
+    Srv(SrvRecord),
+}
+#[derive(Debug, Clone, Default)]
+pub struct DnsResponse {
+    pub answers: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>,
+}
+// The nameserver in the config wins, then the first one in resolv.conf.
+pub fn get_nameserver(nameserver_option: Option<String>) -> Result<SocketAddr, AppError> {
+    let nameserver = match nameserver_option {
+        Some(nameserver) => nameserver,
+        None => std::fs::read_to_string(DEFAULT_RESOLV_CONF_PATH)
+            .map_err(|e| AppError(e.to_string()))?
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .map(|value| value.trim().to_string())
+            .next()
+            .ok_or(AppError(String::from(
+                "Can not find the nameserver in resolv.conf",
+            )))?,
+    };
+    if let Ok(socket_addr) = nameserver.parse::<SocketAddr>() {
+        return Ok(socket_addr);
+    }
+    let ip = nameserver
+        .parse::<IpAddr>()
+        .map_err(|e| AppError(format!("Invalid nameserver {}: {}", nameserver, e)))?;
+    Ok(SocketAddr::new(ip, DEFAULT_DNS_PORT))
+}
+// The query is sent again over tcp if the udp response is truncated, e.g. a SRV record set with
+// many targets.
+pub async fn query(
+    nameserver: SocketAddr,
+    name: &str,
+    record_type: u16,
+) -> Result<DnsResponse, AppError> {
+    let id: u16 = rand::thread_rng().gen();
+    let request = build_query(id, name, record_type)?;
+    let timeout = Duration::from_millis(DEFAULT_DNS_TIMEOUT_MILLIS);
+    let packet = tokio::time::timeout(timeout, query_udp(nameserver, id, &request))
+        .await
+        .map_err(|_| AppError(format!("Query {} to {} timed out", name, nameserver)))??;
+    if packet[2] & TRUNCATED_FLAG == 0 {
+        return parse_response(&packet);
+    }
+    let packet = tokio::time::timeout(timeout, query_tcp(nameserver, id, &request))
+        .await
+        .map_err(|_| {
+            AppError(format!(
+                "Query {} to {} over tcp timed out",
+                name, nameserver
+            ))
+        })??;
+    parse_response(&packet)
+}
+async fn query_udp(nameserver: SocketAddr, id: u16, request: &[u8]) -> Result<Vec<u8>, AppError> {
+    let bind_addr = if nameserver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    socket
+        .connect(nameserver)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    socket
+        .send(request)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    let mut buf = vec![0; MAX_DNS_PACKET_SIZE];
+    loop {
+        let len = socket
+            .recv(&mut buf)
+            .await
+            .map_err(|e| AppError(e.to_string()))?;
+        // Skip the late answers of the other queries.
+        if len >= 12 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+// The messages over tcp are prefixed with their length.
+async fn query_tcp(nameserver: SocketAddr, id: u16, request: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut stream = TcpStream::connect(nameserver)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    let mut message = Vec::with_capacity(request.len() + 2);
+    message.extend_from_slice(&(request.len() as u16).to_be_bytes());
+    message.extend_from_slice(request);
+    stream
+        .write_all(&message)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    let len = stream
+        .read_u16()
+        .await
+        .map_err(|e| AppError(e.to_string()))? as usize;
+    let mut packet = vec![0; len];
+    stream
+        .read_exact(&mut packet)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    if len < 12 || u16::from_be_bytes([packet[0], packet[1]]) != id {
+        return Err(AppError(String::from(
+            "The id of the dns response over tcp does not match",
+        )));
+    }
+    Ok(packet)
+}
+fn build_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, AppError> {
+    let mut packet = Vec::with_capacity(512);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired.
+    packet.extend_from_slice(&0x0100u16.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(AppError(format!("Invalid dns name {}", name)));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&record_type.to_be_bytes());
+    packet.extend_from_slice(&RECORD_CLASS_IN.to_be_bytes());
+    Ok(packet)
+}
+fn parse_response(packet: &[u8]) -> Result<DnsResponse, AppError> {
+    if packet.len() < 12 {
+        return Err(AppError(String::from("The dns response is too short")));
+    }
+    let rcode = packet[3] & 0x0f;
+    if rcode != 0 {
+        return Err(AppError(format!("The dns response code is {}", rcode)));
+    }
+    let question_count = read_u16_as_usize(packet, 4)?;
+    let answer_count = read_u16_as_usize(packet, 6)?;
+    let authority_count = read_u16_as_usize(packet, 8)?;
+    let additional_count = read_u16_as_usize(packet, 10)?;
+    let mut offset = 12;
+    for _ in 0..question_count {
+        let (_, next) = read_name(packet, offset)?;
+        offset = next + 4;
+    }
+    let mut response = DnsResponse::default();
+    for index in 0..(answer_count + authority_count + additional_count) {
+        let (record_option, next) = read_record(packet, offset)?;
+        offset = next;
+        if let Some(record) = record_option {
+            if index < answer_count {
+                response.answers.push(record);
+            } else if index >= answer_count + authority_count {
+                response.additionals.push(record);
+            }
+        }
+    }
+    Ok(response)
+}
+fn read_record(packet: &[u8], offset: usize) -> Result<(Option<DnsRecord>, usize), AppError> {
+    let (name, offset) = read_name(packet, offset)?;
+    let record_type = read_u16(packet, offset)?;
+    let data_len = read_u16(packet, offset + 8)? as usize;
+    let data_start = offset + 10;
+    let data_end = data_start + data_len;
+    let data = packet
+        .get(data_start..data_end)
+        .ok_or(AppError(String::from("The dns record is truncated")))?;
+    let record = match (record_type, data_len) {
+        (RECORD_TYPE_A, 4) => Some(DnsRecord::Address(
+            name,
+            IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+        )),
+        (RECORD_TYPE_AAAA, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(data);
+            Some(DnsRecord::Address(name, IpAddr::V6(Ipv6Addr::from(octets))))
+        }
+        (RECORD_TYPE_SRV, _) if data_len > 6 => {
+            let (target, _) = read_name(packet, data_start + 6)?;
+            Some(DnsRecord::Srv(SrvRecord {
+                priority: read_u16(packet, data_start)?,
+                weight: read_u16(packet, data_start + 2)?,
+                port: read_u16(packet, data_start + 4)?,
+                target,
+            }))
+        }
+        _ => None,
+    };
+    Ok((record, data_end))
+}
+// Returns the name and the offset behind it, the compression pointers are followed.
+fn read_name(packet: &[u8], offset: usize) -> Result<(String, usize), AppError> {
+    let mut labels: Vec<String> = vec![];
+    let mut position = offset;
+    let mut end_option = None;
+    let mut jumps = 0;
+    loop {
+        let len = *packet
+            .get(position)
+            .ok_or(AppError(String::from("The dns name is truncated")))? as usize;
+        if len == 0 {
+            position += 1;
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > 16 {
+                return Err(AppError(String::from("Too many dns compression pointers")));
+            }
+            let pointer = (read_u16(packet, position)? & 0x3fff) as usize;
+            end_option.get_or_insert(position + 2);
+            position = pointer;
+            continue;
+        }
+        let label = packet
+            .get(position + 1..position + 1 + len)
+            .ok_or(AppError(String::from("The dns name is truncated")))?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        position += 1 + len;
+    }
+    Ok((labels.join("."), end_option.unwrap_or(position)))
+}
+fn read_u16_as_usize(packet: &[u8], offset: usize) -> Result<usize, AppError> {
+    read_u16(packet, offset).map(|value| value as usize)
+}
+fn read_u16(packet: &[u8], offset: usize) -> Result<u16, AppError> {
+    packet
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(AppError(String::from("The dns response is truncated")))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // The name of the answers points to the question.
+    const QUESTION_NAME_POINTER: [u8; 2] = [0xc0, 0x0c];
+
+    fn encode_name(name: &str) -> Vec<u8> {
+        let mut encoded = vec![];
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+    fn encode_record(name: &[u8], record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name.to_vec();
+        record.extend_from_slice(&record_type.to_be_bytes());
+        record.extend_from_slice(&RECORD_CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&60u32.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+    fn encode_srv_data(priority: u16, weight: u16, port: u16, target: &str) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&priority.to_be_bytes());
+        data.extend_from_slice(&weight.to_be_bytes());
+        data.extend_from_slice(&port.to_be_bytes());
+        data.extend_from_slice(&encode_name(target));
+        data
+    }
+    // The response repeats the id and the question of the query.
+    fn build_response(
+        query: &[u8],
+        is_truncated: bool,
+        answers: &[Vec<u8>],
+        additionals: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut response = query[..2].to_vec();
+        response.push(0x81 | if is_truncated { TRUNCATED_FLAG } else { 0 });
+        response.push(0x80);
+        response.extend_from_slice(&1u16.to_be_bytes());
+        response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        response.extend_from_slice(&0u16.to_be_bytes());
+        response.extend_from_slice(&(additionals.len() as u16).to_be_bytes());
+        response.extend_from_slice(&query[12..]);
+        for record in answers.iter().chain(additionals) {
+            response.extend_from_slice(record);
+        }
+        response
+    }
+    fn a_response(query: &[u8], is_truncated: bool) -> Vec<u8> {
+        let answers = if is_truncated {
+            vec![]
+        } else {
+            vec![
+                encode_record(&QUESTION_NAME_POINTER, RECORD_TYPE_A, &[10, 0, 0, 1]),
+                encode_record(&QUESTION_NAME_POINTER, RECORD_TYPE_A, &[10, 0, 0, 2]),
+            ]
+        };
+        build_response(query, is_truncated, &answers, &[])
+    }
+    // The stub answers the udp queries, and the tcp queries on the same port.
+    async fn start_nameserver(respond: fn(&[u8], bool) -> Vec<u8>) -> SocketAddr {
+        let (listener, socket) = loop {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            if let Ok(socket) = UdpSocket::bind(addr).await {
+                break (listener, socket);
+            }
+        };
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; MAX_DNS_PACKET_SIZE];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let response = respond(&buf[..len], false);
+                socket.send_to(&response, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let len = stream.read_u16().await.unwrap() as usize;
+                let mut query = vec![0; len];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = respond(&query, true);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_query_a_records() {
+        let nameserver = start_nameserver(|query, _| a_response(query, false)).await;
+        let response = query(nameserver, "backend.example.com", RECORD_TYPE_A)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.answers,
+            vec![
+                DnsRecord::Address(
+                    String::from("backend.example.com"),
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))
+                ),
+                DnsRecord::Address(
+                    String::from("backend.example.com"),
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))
+                ),
+            ]
+        );
+        assert!(response.additionals.is_empty());
+    }
+    #[tokio::test]
+    async fn test_query_srv_records() {
+        let nameserver = start_nameserver(|query, _| {
+            let answers = vec![
+                encode_record(
+                    &QUESTION_NAME_POINTER,
+                    RECORD_TYPE_SRV,
+                    &encode_srv_data(10, 60, 8080, "node1.example.com"),
+                ),
+                encode_record(
+                    &QUESTION_NAME_POINTER,
+                    RECORD_TYPE_SRV,
+                    &encode_srv_data(20, 40, 8081, "node2.example.com"),
+                ),
+            ];
+            let additionals = vec![encode_record(
+                &encode_name("node1.example.com"),
+                RECORD_TYPE_A,
+                &[10, 0, 0, 3],
+            )];
+            build_response(query, false, &answers, &additionals)
+        })
+        .await;
+        let response = query(nameserver, "_http._tcp.example.com", RECORD_TYPE_SRV)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.answers,
+            vec![
+                DnsRecord::Srv(SrvRecord {
+                    priority: 10,
+                    weight: 60,
+                    port: 8080,
+                    target: String::from("node1.example.com"),
+                }),
+                DnsRecord::Srv(SrvRecord {
+                    priority: 20,
+                    weight: 40,
+                    port: 8081,
+                    target: String::from("node2.example.com"),
+                }),
+            ]
+        );
+        assert_eq!(
+            response.additionals,
+            vec![DnsRecord::Address(
+                String::from("node1.example.com"),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3))
+            )]
+        );
+    }
+    // The truncated udp response has no answers, they are only sent over tcp.
+    #[tokio::test]
+    async fn test_query_truncated_response_over_tcp() {
+        let nameserver = start_nameserver(|query, is_tcp| a_response(query, !is_tcp)).await;
+        let response = query(nameserver, "backend.example.com", RECORD_TYPE_A)
+            .await
+            .unwrap();
+        assert_eq!(response.answers.len(), 2);
+    }
+    #[tokio::test]
+    async fn test_query_error_response() {
+        let nameserver = start_nameserver(|query, _| {
+            let mut response = build_response(query, false, &[], &[]);
+            // NXDOMAIN
+            response[3] |= 3;
+            response
+        })
+        .await;
+        assert!(query(nameserver, "missing.example.com", RECORD_TYPE_A)
+            .await
+            .is_err());
+    }
+}
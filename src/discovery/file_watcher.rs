@@ -0,0 +1,119 @@
+use crate::vojo::app_error::AppError;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+const INOTIFY_BUFFER_SIZE: usize = 4096;
+
+// Watches the directories of the endpoint files with inotify. The directory is watched
+// instead of the file, because the deploy tooling usually replaces the file with a rename.
+pub struct FileWatcher {
+    inotify_fd: AsyncFd<OwnedFd>,
+    watched_dirs: HashMap<i32, PathBuf>,
+}
+impl FileWatcher {
+    pub fn new() -> Result<Self, AppError> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(AppError(std::io::Error::last_os_error().to_string()));
+        }
+        let owned_fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let inotify_fd = AsyncFd::with_interest(owned_fd, Interest::READABLE)
+            .map_err(|e| AppError(e.to_string()))?;
+        Ok(FileWatcher {
+            inotify_fd,
+            watched_dirs: HashMap::new(),
+        })
+    }
+    pub fn watch(&mut self, path: &Path) -> Result<(), AppError> {
+        let dir = get_watch_dir(path);
+        if self.watched_dirs.values().any(|item| *item == dir) {
+            return Ok(());
+        }
+        let dir_str =
+            CString::new(dir.to_string_lossy().as_bytes()).map_err(|e| AppError(e.to_string()))?;
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MOVED_TO
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MODIFY;
+        let wd =
+            unsafe { libc::inotify_add_watch(self.inotify_fd.as_raw_fd(), dir_str.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(AppError(format!(
+                "Watch the directory {} error,the error is {}",
+                dir.display(),
+                std::io::Error::last_os_error()
+            )));
+        }
+        self.watched_dirs.insert(wd, dir);
+        Ok(())
+    }
+    // Waits for the next events and returns the paths which have been changed.
+    pub async fn changed_paths(&self) -> Result<Vec<PathBuf>, AppError> {
+        loop {
+            let mut guard = self
+                .inotify_fd
+                .readable()
+                .await
+                .map_err(|e| AppError(e.to_string()))?;
+            let mut buf = [0u8; INOTIFY_BUFFER_SIZE];
+            let read_result = guard.try_io(|inner| {
+                let len = unsafe {
+                    libc::read(
+                        inner.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    )
+                };
+                if len < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(len as usize)
+                }
+            });
+            let len = match read_result {
+                Ok(result) => result.map_err(|e| AppError(e.to_string()))?,
+                Err(_would_block) => continue,
+            };
+            return Ok(self.parse_events(&buf[..len]));
+        }
+    }
+    fn parse_events(&self, buf: &[u8]) -> Vec<PathBuf> {
+        let header_size = std::mem::size_of::<libc::inotify_event>();
+        let mut paths = vec![];
+        let mut offset = 0;
+        while offset + header_size <= buf.len() {
+            let event = unsafe {
+                std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+            };
+            let name_start = offset + header_size;
+            let name_end = (name_start + event.len as usize).min(buf.len());
+            let name_bytes = &buf[name_start..name_end];
+            let name_len = name_bytes
+                .iter()
+                .position(|item| *item == 0)
+                .unwrap_or(name_bytes.len());
+            if let Some(dir) = self.watched_dirs.get(&event.wd) {
+                let name = String::from_utf8_lossy(&name_bytes[..name_len]).to_string();
+                paths.push(dir.join(name));
+            }
+            offset = name_end;
+        }
+        paths
+    }
+}
+pub fn get_watch_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+// The path of the config and the path of the event point at the same file.
+pub fn is_same_file(path: &Path, changed_path: &Path) -> bool {
+    get_watch_dir(path) == get_watch_dir(changed_path)
+        && path.file_name() == changed_path.file_name()
+}
@@ -0,0 +1,3 @@
+pub mod discovery_service;
+pub mod dns_resolver;
+pub mod file_watcher;
@@ -1,7 +1,12 @@
+#![cfg_attr(test, feature(test))]
+use constants::common_constants::{ENV_REGION, ENV_ZONE};
+use std::env;
 use vojo::app_error::AppError;
 use vojo::handler::Handler;
+use vojo::route::Locality;
 mod constants;
 mod control_plane;
+mod discovery;
 mod middleware;
 mod proxy;
 mod vojo;
@@ -17,6 +22,9 @@ use crate::control_plane::rest_api::start_control_plane;
 fn main() -> Result<(), AppError> {
     std::thread::scope(|s| {
         let handler = Handler::new();
+        if let Ok(mut app_config) = handler.shared_app_config.write() {
+            app_config.static_config.locality = get_locality_from_env();
+        }
         let handle_clone = handler.clone();
         s.spawn(move || {
             let _ = starts_control_plane(handle_clone);
@@ -24,6 +32,14 @@ fn main() -> Result<(), AppError> {
     });
     Ok(())
 }
+fn get_locality_from_env() -> Option<Locality> {
+    let zone = env::var(ENV_ZONE).ok();
+    let region = env::var(ENV_REGION).ok();
+    if zone.is_none() && region.is_none() {
+        return None;
+    }
+    Some(Locality { zone, region })
+}
 fn starts_control_plane(hander: Handler) -> Result<(), AppError> {
     let rt = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(1)
@@ -1,11 +1,14 @@
+use futures::future::LocalBoxFuture;
 use futures::task::Context;
 use futures::task::Poll;
-use monoio_http::common::request::Request;
+use monoio_http::common::response::Response;
 
 use tower::Service;
 
+use crate::constants::common_constants::ACCESS_LOG_TARGET;
 use crate::vojo::gateway_request::GatewayRequest;
-// A middleware that logs requests before forwarding them to another service
+use crate::vojo::size_limits::SizeLimitViolation;
+// A middleware that writes the access log after the response is built
 pub struct LogService<S> {
     pub target: &'static str,
     pub service: S,
@@ -13,18 +16,43 @@ pub struct LogService<S> {
 
 impl<S> Service<GatewayRequest> for LogService<S>
 where
-    S: Service<GatewayRequest>,
+    S: Service<GatewayRequest, Response = Response>,
+    S::Future: 'static,
 {
     type Response = S::Response;
     type Error = S::Error;
-    type Future = S::Future;
+    type Future = LocalBoxFuture<'static, Result<S::Response, S::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.service.poll_ready(cx)
     }
 
     fn call(&mut self, request: GatewayRequest) -> Self::Future {
-        // info!("ip is:{}", request.remote_ip);
-        self.service.call(request)
+        let remote_ip = request.remote_ip.clone();
+        let method = request.request.method().clone();
+        // The query is left out, it may carry the credentials.
+        let path = request.request.uri().path().to_string();
+        let future = self.service.call(request);
+        Box::pin(async move {
+            let result = future.await;
+            if let Ok(response) = &result {
+                // The reason is only set when the request is rejected by the gateway.
+                let reason = response
+                    .extensions()
+                    .get::<SizeLimitViolation>()
+                    .map(|item| item.get_reason())
+                    .unwrap_or("-");
+                info!(
+                    target: ACCESS_LOG_TARGET,
+                    "{} {} {} {} reason={}",
+                    remote_ip,
+                    method,
+                    path,
+                    response.status().as_u16(),
+                    reason
+                );
+            }
+            result
+        })
     }
 }
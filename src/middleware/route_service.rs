@@ -1,47 +1,699 @@
-use crate::middleware::log_service::LogService;
+use crate::constants::common_constants::DEFAULT_HTTP_TIMEOUT;
+use crate::constants::common_constants::DEFAULT_MIRROR_TIMEOUT_MILLIS;
+use crate::constants::common_constants::DENY_RESPONSE;
+use crate::constants::common_constants::MAX_DECOMPRESSED_REQUEST_SIZE;
+use crate::constants::common_constants::NOT_FOUND;
+use crate::constants::common_constants::REQUEST_ID_HEADER;
+use crate::constants::common_constants::SHADOW_HOST_SUFFIX;
+use crate::proxy::static_file::send_static_file;
+use crate::proxy::upstream_pool::UpstreamError;
+use crate::proxy::upstream_pool::UpstreamPool;
+use crate::vojo::app_config::Route;
 use crate::vojo::app_error::AppError;
-use crate::vojo::handler::Handler;
+use crate::vojo::compression::decompress_body;
+use crate::vojo::compression::get_request_encoding;
+use crate::vojo::cors::get_preflight_method;
+use crate::vojo::header_operations::get_header_name;
+use crate::vojo::header_operations::HeaderTemplateContext;
+use crate::vojo::route_action::RouteAction;
+use crate::vojo::size_limits::SizeLimitViolation;
+use crate::vojo::size_limits::SizeLimits;
+use uuid::Uuid;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 
-use http::{response::Builder, HeaderMap, StatusCode};
+use futures::future::LocalBoxFuture;
+use http::request::Parts;
+use http::uri::PathAndQuery;
+use http::{
+    header::{
+        CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LOCATION, SET_COOKIE,
+    },
+    HeaderMap, HeaderValue, Method, StatusCode, Uri, Version,
+};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
 
 use monoio_http::{
-    common::{error::HttpError, request::Request, response::Response},
-    h1::{
-        codec::{decoder::RequestDecoder, encoder::GenericEncoder},
-        payload::{FixedPayload, Payload},
+    common::{
+        body::{Body, BodyExt, HttpBody},
+        request::Request,
+        response::Response,
     },
-    util::spsc::{spsc_pair, SPSCReceiver},
+    h1::payload::{FixedPayload, Payload},
 };
 
 use crate::vojo::gateway_request::GatewayRequest;
+use crate::vojo::route::get_cookie_value;
+use crate::vojo::route::BaseRoute;
+// Every error is answered, so the client and the pipelined requests do not wait for a response.
 pub async fn handle_request(gateway_request: GatewayRequest) -> Result<Response, AppError> {
-    let resp_result = gateway_request
-        .client
-        .get("http://backend:8080/get")
-        .send()
-        .await;
-
-    match resp_result {
-        Ok(resp) => {
-            info!("has receive response,header is:{:?}", resp.headers());
-            let http_resp = resp.bytes().await.unwrap();
-            let res = Payload::Fixed(FixedPayload::new(http_resp));
+    match handle_request_with_error(gateway_request).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            error!("Handle the request error,{}", e);
+            Ok(build_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Bytes::from(e.to_string()),
+            ))
+        }
+    }
+}
+async fn handle_request_with_error(gateway_request: GatewayRequest) -> Result<Response, AppError> {
+    let GatewayRequest {
+        port,
+        request,
+        remote_ip,
+        upstream_pool,
+        http_cache,
+        handler,
+        ..
+    } = gateway_request;
+    let (mut parts, body) = request.into_parts();
+    let (route_option, action_route_option, scheme, service_compression, service_size_limits) = {
+        let app_config = handler
+            .shared_app_config
+            .read()
+            .map_err(|e| AppError(e.to_string()))?;
+        let local_locality = app_config.static_config.locality.clone();
+        let api_service = app_config
+            .api_service_config
+            .get(&port)
+            .ok_or(AppError(format!("Can not find port {} in config.", port)))?;
+        let scheme = api_service.service_config.server_type.get_scheme();
+        let service_compression = api_service.service_config.compression.clone();
+        let service_size_limits = api_service.service_config.size_limits.clone();
+        if api_service.service_config.normalize_path {
+            parts.uri = get_normalized_uri(&parts.uri)?;
+        }
+        let mut route_option = None;
+        let mut action_route_option = None;
+        // The preflight is matched with the method of the actual request.
+        let preflight_method = get_preflight_method(&parts);
+        for index in api_service
+            .service_config
+            .get_route_candidates(parts.uri.path())?
+        {
+            let route = &api_service.service_config.routes[index];
+            let method = match (&route.cors, &preflight_method) {
+                (Some(_), Some(preflight_method)) => preflight_method,
+                _ => &parts.method,
+            };
+            if let Some(final_path) = route.is_matched(method, &parts.uri, &parts.headers)? {
+                if route.action.is_some() {
+                    action_route_option = Some(route.clone());
+                    break;
+                }
+                let base_route_result = route.get_base_route(
+                    parts.headers.clone(),
+                    remote_ip.clone(),
+                    parts.uri.clone(),
+                    local_locality.clone(),
+                );
+                // The root is canonicalized with the route table, not on every request.
+                let static_root = match &base_route_result {
+                    Ok(base_route) if base_route.try_file.is_some() => Some(
+                        api_service
+                            .service_config
+                            .get_static_root(&base_route.endpoint)?,
+                    ),
+                    _ => None,
+                };
+                let mirror_base_route = route.get_mirror_base_route(
+                    parts.headers.clone(),
+                    remote_ip.clone(),
+                    parts.uri.clone(),
+                    local_locality.clone(),
+                );
+                route_option = Some((
+                    route.clone(),
+                    final_path,
+                    base_route_result,
+                    mirror_base_route,
+                    static_root,
+                ));
+                break;
+            }
+        }
+        (
+            route_option,
+            action_route_option,
+            scheme,
+            service_compression,
+            service_size_limits,
+        )
+    };
+    let matched_route = action_route_option
+        .as_ref()
+        .or(route_option.as_ref().map(|item| &item.0));
+    let size_limits = SizeLimits::merge(
+        matched_route.and_then(|item| item.size_limits.as_ref()),
+        service_size_limits.as_ref(),
+    );
+    if let Some(violation) = size_limits.check_request_head(&parts) {
+        return Ok(build_violation_response(violation));
+    }
+    if let Some(route) = action_route_option {
+        return handle_route_action(route, parts, remote_ip, scheme).await;
+    }
+    let (mut route, final_path, base_route, mirror_base_route, static_root) = match route_option {
+        Some((route, final_path, Ok(base_route), mirror_base_route, static_root)) => (
+            route,
+            final_path,
+            base_route,
+            mirror_base_route,
+            static_root,
+        ),
+        Some((_, _, Err(e), _, _)) => {
+            return Ok(build_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                Bytes::from(e.to_string()),
+            ))
+        }
+        None => {
+            return Ok(build_response(
+                StatusCode::NOT_FOUND,
+                Bytes::from_static(NOT_FOUND.as_bytes()),
+            ))
+        }
+    };
+    // The preflight carries no credentials, so it is answered before the authentication.
+    if let (Some(cors), Some(_)) = (&route.cors, get_preflight_method(&parts)) {
+        let response = match cors.get_preflight_headers(&parts.headers)? {
+            Some(headers) => {
+                let mut response = build_response(StatusCode::NO_CONTENT, Bytes::new());
+                response.headers_mut().extend(headers);
+                response
+            }
+            None => build_response(StatusCode::FORBIDDEN, Bytes::new()),
+        };
+        return Ok(response);
+    }
+    // The context is only built when it is used, the request id may be generated.
+    let header_template_context = route
+        .has_header_operations()
+        .then(|| HeaderTemplateContext {
+            remote_ip: remote_ip.clone(),
+            route_id: route.route_id.clone(),
+            upstream: base_route.endpoint.clone(),
+            request_id: get_request_id(&parts.headers),
+        });
+    if !route
+        .is_allowed(remote_ip.clone(), Some(parts.headers.clone()))
+        .await?
+    {
+        return Ok(build_response(
+            StatusCode::FORBIDDEN,
+            Bytes::from_static(DENY_RESPONSE.as_bytes()),
+        ));
+    }
+    let route_headers = parts.headers.clone();
+    if let Some(forwarding_headers) = &route.forwarding_headers {
+        forwarding_headers.apply(&mut parts.headers, &remote_ip, scheme, port)?;
+    }
+    if let Some(host) = get_upstream_host(&route, &base_route.endpoint)? {
+        parts.headers.insert(HOST, host);
+    }
+    if let Some(header_template_context) = &header_template_context {
+        route.apply_request_headers(&mut parts.headers, header_template_context)?;
+    }
+    let body = match size_limits.max_request_body_size {
+        Some(max_size) if matches!(body, Payload::Stream(_)) => {
+            match get_limited_body_bytes(body, max_size).await? {
+                Some(body) => get_payload(body),
+                None => {
+                    return Ok(build_violation_response(
+                        SizeLimitViolation::RequestBodyTooLarge,
+                    ))
+                }
+            }
+        }
+        _ => body,
+    };
+    // The upstream pool reads the response limit from the route.
+    route.size_limits = Some(size_limits.clone());
+    let compression = route.compression.clone().or(service_compression);
+    let body = match (&compression, get_request_encoding(&parts.headers)) {
+        (Some(compression), Some(encoding)) if compression.decompress_requests => {
+            let max_size = size_limits
+                .max_request_body_size
+                .unwrap_or(MAX_DECOMPRESSED_REQUEST_SIZE);
+            // The encoded body is limited before it is read, so it is never buffered without bound.
+            let Some(body) = get_limited_body_bytes(body, max_size).await? else {
+                return Ok(build_violation_response(
+                    SizeLimitViolation::RequestBodyTooLarge,
+                ));
+            };
+            let body = match decompress_body(encoding, &body, max_size) {
+                Ok(Some(body)) => body,
+                Ok(None) => {
+                    return Ok(build_violation_response(
+                        SizeLimitViolation::RequestBodyTooLarge,
+                    ))
+                }
+                Err(e) => return Ok(build_response(StatusCode::BAD_REQUEST, Bytes::from(e.0))),
+            };
+            parts.headers.remove(CONTENT_ENCODING);
+            parts
+                .headers
+                .insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+            get_payload(body)
+        }
+        _ => body,
+    };
+    let method = parts.method.clone();
+    // The static files are served by the worker itself.
+    let mut response = if let Some(static_root) = &static_root {
+        send_static_file(static_root, &base_route, &final_path, &parts)
+            .await
+            .unwrap_or_else(|e| {
+                build_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Bytes::from(e.to_string()),
+                )
+            })
+    } else if let (Some(cache_config), true) = (
+        &route.cache,
+        parts.method == Method::GET || parts.method == Method::HEAD,
+    ) {
+        let fetch = get_cached_request_fetch(
+            upstream_pool,
+            port,
+            route.clone(),
+            base_route.clone(),
+            final_path,
+            &parts,
+        );
+        http_cache
+            .send_request(cache_config, &route.route_id, &parts, fetch)
+            .await?
+    } else {
+        if route.cache.is_some() {
+            http_cache.invalidate(&route.route_id, &parts)?;
+        }
+        send_upstream_request(
+            &upstream_pool,
+            port,
+            &route,
+            &base_route,
+            mirror_base_route,
+            &final_path,
+            parts,
+            body,
+        )
+        .await?
+    };
+    if let Some(session_affinity) = &route.session_affinity {
+        let affinity_route_id = get_cookie_value(&route_headers, &session_affinity.cookie_name);
+        if affinity_route_id.as_deref() != Some(base_route.base_route_id.as_str()) {
+            let set_cookie = session_affinity.get_set_cookie(&base_route.base_route_id);
+            let header_value =
+                HeaderValue::from_str(&set_cookie).map_err(|e| AppError(e.to_string()))?;
+            response.headers_mut().append(SET_COOKIE, header_value);
+        }
+    }
+    if let Some(header_template_context) = &header_template_context {
+        route.apply_response_headers(response.headers_mut(), header_template_context)?;
+    }
+    if let Some(cors) = &route.cors {
+        cors.apply_response_headers(&route_headers, response.headers_mut())?;
+    }
+    if let Some(compression) = &compression {
+        response = compression
+            .compress_response(&method, &route_headers, response)
+            .await?;
+    }
+    Ok(response)
+}
+#[allow(clippy::too_many_arguments)]
+async fn send_upstream_request(
+    upstream_pool: &UpstreamPool,
+    port: i32,
+    route: &Route,
+    base_route: &BaseRoute,
+    mirror_base_route: Option<BaseRoute>,
+    final_path: &str,
+    mut parts: Parts,
+    body: Payload,
+) -> Result<Response, AppError> {
+    let body = match mirror_base_route {
+        Some(mirror_base_route) => {
+            let body = get_body_bytes(body).await?;
+            send_mirror_request(
+                upstream_pool,
+                format!("{}-{}-mirror", port, route.route_id),
+                route,
+                &parts,
+                get_upstream_uri(&mirror_base_route.endpoint, final_path, parts.uri.query())?,
+                get_payload(body.clone()),
+            );
+            get_payload(body)
+        }
+        None => body,
+    };
+    parts.uri = get_upstream_uri(&base_route.endpoint, final_path, parts.uri.query())?;
+    parts.version = Version::HTTP_11;
 
-            Ok(Builder::new()
-                .status(StatusCode::OK)
-                .header("Server", "monoio-http-demo")
-                .body(res)
-                .unwrap())
+    let pool_id = format!("{}-{}", port, route.route_id);
+    let in_flight_guard = base_route.load_status.start_request();
+    let send_future = upstream_pool.send_request(
+        &pool_id,
+        route,
+        HttpBody::request(Request::from_parts(parts, body)),
+    );
+    let timeout = Duration::from_secs(DEFAULT_HTTP_TIMEOUT);
+    let send_result = monoio::time::timeout(timeout, send_future).await;
+    // The failures of the upstream itself count against its liveness, the rejected ones do not.
+    let is_alive = matches!(
+        send_result,
+        Ok(Ok(_)) | Ok(Err(UpstreamError::SizeLimit(_)))
+    );
+    base_route.load_status.update_liveness(is_alive);
+    let response = match send_result {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(UpstreamError::SizeLimit(violation))) => build_violation_response(violation),
+        Ok(Err(e)) => build_response(StatusCode::BAD_GATEWAY, Bytes::from(e.to_string())),
+        Err(_) => build_response(
+            StatusCode::GATEWAY_TIMEOUT,
+            Bytes::from_static(b"The upstream timed out"),
+        ),
+    };
+    drop(in_flight_guard);
+    Ok(response)
+}
+// The conditional headers of the client are replaced, so the full response can be cached, the
+// cache answers the conditional requests itself.
+fn get_cached_request_fetch(
+    upstream_pool: UpstreamPool,
+    port: i32,
+    route: Route,
+    base_route: BaseRoute,
+    final_path: String,
+    parts: &Parts,
+) -> impl Fn(HeaderMap<HeaderValue>) -> LocalBoxFuture<'static, Result<Response, AppError>> {
+    let mut headers = parts.headers.clone();
+    headers.remove(IF_NONE_MATCH);
+    headers.remove(IF_MODIFIED_SINCE);
+    let context = Rc::new((
+        upstream_pool,
+        route,
+        base_route,
+        final_path,
+        parts.method.clone(),
+        parts.uri.clone(),
+        headers,
+    ));
+    move |conditional_headers| {
+        let context = context.clone();
+        Box::pin(async move {
+            let (upstream_pool, route, base_route, final_path, method, uri, headers) = &*context;
+            let mut parts = http::Request::new(()).into_parts().0;
+            parts.method = method.clone();
+            parts.uri = uri.clone();
+            parts.headers = headers.clone();
+            parts.headers.extend(conditional_headers);
+            send_upstream_request(
+                upstream_pool,
+                port,
+                route,
+                base_route,
+                None,
+                final_path,
+                parts,
+                Payload::None,
+            )
+            .await
+        })
+    }
+}
+// The request id of the client is kept.
+fn get_request_id(headers: &HeaderMap<HeaderValue>) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|item| item.to_str().ok())
+        .map(|item| item.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+async fn handle_route_action(
+    route: Route,
+    parts: Parts,
+    remote_ip: String,
+    scheme: &str,
+) -> Result<Response, AppError> {
+    if !route
+        .is_allowed(remote_ip.clone(), Some(parts.headers.clone()))
+        .await?
+    {
+        return Ok(build_response(
+            StatusCode::FORBIDDEN,
+            Bytes::from_static(DENY_RESPONSE.as_bytes()),
+        ));
+    }
+    let action = route
+        .action
+        .as_ref()
+        .ok_or(AppError(String::from("The route has no action")))?;
+    let status = action.get_status()?;
+    let mut response = match action {
+        RouteAction::Redirect(redirect) => {
+            let matched_prefix = route.matcher.as_ref().map(|item| item.prefix.as_str());
+            let location =
+                redirect.get_location(&parts.uri, &parts.headers, scheme, matched_prefix)?;
+            let mut response = build_response(status, Bytes::new());
+            response.headers_mut().insert(
+                LOCATION,
+                HeaderValue::from_str(&location).map_err(|e| AppError(e.to_string()))?,
+            );
+            response
+        }
+        RouteAction::DirectResponse(direct_response) => {
+            let body = match (&direct_response.body, &direct_response.body_file) {
+                (Some(body), _) => Bytes::from(body.clone()),
+                (None, Some(body_file)) => monoio::fs::read(body_file)
+                    .await
+                    .map(Bytes::from)
+                    .map_err(|e| AppError(format!("Read the file {} error,{}", body_file, e)))?,
+                (None, None) => Bytes::new(),
+            };
+            let mut response = build_response(status, body);
+            for (key, value) in direct_response.headers.iter() {
+                response.headers_mut().insert(
+                    get_header_name(key)?,
+                    HeaderValue::from_str(value).map_err(|e| AppError(e.to_string()))?,
+                );
+            }
+            response
+        }
+    };
+    if route.response_headers.is_some() {
+        let header_template_context = HeaderTemplateContext {
+            remote_ip,
+            route_id: route.route_id.clone(),
+            upstream: String::new(),
+            request_id: get_request_id(&parts.headers),
+        };
+        route.apply_response_headers(response.headers_mut(), &header_template_context)?;
+    }
+    Ok(response)
+}
+// The mirror needs its own copy of the body, so the body is read into the memory.
+async fn get_body_bytes(body: Payload) -> Result<Bytes, AppError> {
+    if let Payload::None = body {
+        return Ok(Bytes::new());
+    }
+    let (_, body) = HttpBody::request(Request::new(body)).into_parts();
+    body.bytes().await.map_err(|e| AppError(e.to_string()))
+}
+// Returns None as soon as the body exceeds the limit, the rest of the body is not read.
+async fn get_limited_body_bytes(body: Payload, max_size: usize) -> Result<Option<Bytes>, AppError> {
+    let (_, mut body) = HttpBody::request(Request::new(body)).into_parts();
+    let mut bytes = BytesMut::new();
+    while let Some(data_result) = body.next_data().await {
+        let data = data_result.map_err(|e| AppError(e.to_string()))?;
+        if bytes.len() + data.len() > max_size {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&data);
+    }
+    Ok(Some(bytes.freeze()))
+}
+fn get_payload(bytes: Bytes) -> Payload {
+    if bytes.is_empty() {
+        Payload::None
+    } else {
+        Payload::Fixed(FixedPayload::new(bytes))
+    }
+}
+// Fire and forget, the failures of the mirror never affect the main response.
+fn send_mirror_request(
+    upstream_pool: &UpstreamPool,
+    pool_id: String,
+    route: &Route,
+    parts: &Parts,
+    uri: Uri,
+    body: Payload,
+) {
+    let mut request_builder = Request::builder()
+        .method(parts.method.clone())
+        .uri(uri)
+        .version(Version::HTTP_11);
+    for (key, value) in parts.headers.iter() {
+        if key == HOST {
+            let shadow_host = get_shadow_host(value.to_str().unwrap_or_default());
+            request_builder = request_builder.header(HOST, shadow_host);
+        } else {
+            request_builder = request_builder.header(key, value);
         }
+    }
+    let request = match request_builder.body(body) {
+        Ok(request) => request,
         Err(e) => {
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Payload::Fixed(FixedPayload::new(Bytes::from(
-                    e.to_string(),
-                ))))
-                .unwrap());
+            error!("Build the mirror request error,{}", e);
+            return;
+        }
+    };
+    let Some(mirror) = &route.mirror else {
+        return;
+    };
+    let upstream_pool = upstream_pool.clone();
+    let mirror_route = mirror.get_upstream_route(route);
+    monoio::spawn(async move {
+        let timeout = Duration::from_millis(DEFAULT_MIRROR_TIMEOUT_MILLIS);
+        let send_future =
+            upstream_pool.send_request(&pool_id, &mirror_route, HttpBody::request(request));
+        match monoio::time::timeout(timeout, send_future).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => debug!("The mirror request error,{}", e),
+            Err(_) => debug!("The mirror request timed out"),
+        }
+    });
+}
+fn get_upstream_host(route: &Route, endpoint: &str) -> Result<Option<HeaderValue>, AppError> {
+    let host = if let Some(host_rewrite) = &route.host_rewrite {
+        host_rewrite.clone()
+    } else if route.auto_host_rewrite {
+        let uri = endpoint
+            .parse::<Uri>()
+            .map_err(|e| AppError(e.to_string()))?;
+        match uri.authority() {
+            Some(authority) => authority.to_string(),
+            None => return Ok(None),
         }
+    } else {
+        return Ok(None);
+    };
+    HeaderValue::from_str(&host)
+        .map(Some)
+        .map_err(|e| AppError(e.to_string()))
+}
+fn get_normalized_uri(uri: &Uri) -> Result<Uri, AppError> {
+    let path = uri.path();
+    if !path.starts_with('/') {
+        return Ok(uri.clone());
+    }
+    let normalized_path = normalize_path(path);
+    if normalized_path == path {
+        return Ok(uri.clone());
+    }
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", normalized_path, query),
+        None => normalized_path,
+    };
+    let mut uri_parts = uri.clone().into_parts();
+    uri_parts.path_and_query =
+        Some(PathAndQuery::from_str(&path_and_query).map_err(|e| AppError(e.to_string()))?);
+    Uri::from_parts(uri_parts).map_err(|e| AppError(e.to_string()))
+}
+// Only the unreserved characters are decoded so an encoded slash never becomes a separator, the
+// other escapes are kept in the upper case. Then the duplicate slashes are merged and the dot
+// segments are removed, the path never goes above the root.
+fn normalize_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            let high = (bytes[index + 1] as char).to_digit(16);
+            let low = (bytes[index + 2] as char).to_digit(16);
+            if let (Some(high), Some(low)) = (high, low) {
+                let byte = (high * 16 + low) as u8;
+                if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                    decoded.push(byte);
+                } else {
+                    decoded.push(b'%');
+                    decoded.push(bytes[index + 1].to_ascii_uppercase());
+                    decoded.push(bytes[index + 2].to_ascii_uppercase());
+                }
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+    let mut segments = vec![];
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    let mut normalized_path = format!("/{}", segments.join("/"));
+    let is_directory =
+        decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if is_directory && !segments.is_empty() {
+        normalized_path.push('/');
     }
+    normalized_path
+}
+// The suffix is added to the host name, the port is kept.
+fn get_shadow_host(host: &str) -> String {
+    if let Some((host_name, port)) = host.rsplit_once(':') {
+        let is_port = !port.is_empty() && port.bytes().all(|item| item.is_ascii_digit());
+        if is_port && (!host_name.contains(':') || host_name.ends_with(']')) {
+            return format!("{}{}:{}", host_name, SHADOW_HOST_SUFFIX, port);
+        }
+    }
+    format!("{}{}", host, SHADOW_HOST_SUFFIX)
+}
+fn get_upstream_uri(endpoint: &str, path: &str, query: Option<&str>) -> Result<Uri, AppError> {
+    let mut upstream_uri = format!(
+        "{}/{}",
+        endpoint.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    if let Some(query) = query {
+        // The rewritten path may carry its own query.
+        upstream_uri.push(if path.contains('?') { '&' } else { '?' });
+        upstream_uri.push_str(query);
+    }
+    upstream_uri
+        .parse::<Uri>()
+        .map_err(|e| AppError(e.to_string()))
+}
+fn build_response(status: StatusCode, body: Bytes) -> Response {
+    Response::builder()
+        .status(status)
+        .body(Payload::Fixed(FixedPayload::new(body)))
+        .unwrap()
+}
+// The violation is kept in the extensions for the access log, the connection is closed after the
+// response because the rest of the request is not read.
+pub fn build_violation_response(violation: SizeLimitViolation) -> Response {
+    let status = violation.get_status();
+    let mut response = build_response(
+        status,
+        Bytes::from_static(status.canonical_reason().unwrap_or_default().as_bytes()),
+    );
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    response.extensions_mut().insert(violation);
+    response
 }
@@ -0,0 +1,634 @@
+use crate::constants::common_constants::CACHE_STATUS_HEADER;
+use crate::vojo::app_error::AppError;
+use crate::vojo::cache_config::CacheConfig;
+use crate::vojo::cache_config::CachePurgeLog;
+use bytes::Bytes;
+use futures::channel::oneshot;
+use http::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, DATE, ETAG, EXPIRES, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, SET_COOKIE, VARY,
+};
+use http::request::Parts;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use monoio_http::common::body::Body;
+use monoio_http::common::response::Response;
+use monoio_http::h1::payload::{FixedPayload, Payload};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+// The statuses which may be cached without the explicit freshness in RFC 9110.
+const CACHEABLE_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+// The host and the path with the query.
+type CacheKey = (String, String);
+
+struct CacheEntry {
+    // The request headers named by the Vary of the response.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    status: StatusCode,
+    headers: HeaderMap<HeaderValue>,
+    body: Bytes,
+    stored_at: Instant,
+    initial_age: Duration,
+    fresh_lifetime: Duration,
+    stale_while_revalidate: Duration,
+    // The stale entry is never served without the revalidation.
+    must_revalidate: bool,
+}
+impl CacheEntry {
+    fn get_age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+    fn is_fresh(&self) -> bool {
+        self.get_age() < self.fresh_lifetime
+    }
+    fn can_serve_stale(&self) -> bool {
+        !self.must_revalidate && self.get_age() < self.fresh_lifetime + self.stale_while_revalidate
+    }
+    fn is_vary_matched(&self, headers: &HeaderMap<HeaderValue>) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+    fn get_size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>()
+    }
+    fn get_conditional_headers(&self) -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        headers
+    }
+}
+#[derive(Default)]
+struct CachedResource {
+    variants: Vec<CacheEntry>,
+    size: usize,
+    last_used: u64,
+}
+// The entries of one route, evicted in the least recently used order.
+#[derive(Default)]
+struct CachePartition {
+    resources: HashMap<CacheKey, CachedResource>,
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    size: usize,
+}
+impl CachePartition {
+    fn touch(&mut self, key: &CacheKey) {
+        let Some(resource) = self.resources.get_mut(key) else {
+            return;
+        };
+        self.lru.remove(&resource.last_used);
+        self.tick += 1;
+        resource.last_used = self.tick;
+        self.lru.insert(self.tick, key.clone());
+    }
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(resource) = self.resources.remove(key) {
+            self.lru.remove(&resource.last_used);
+            self.size -= resource.size;
+        }
+    }
+    fn insert(&mut self, key: CacheKey, entry: CacheEntry, max_size: usize) {
+        let resource = self.resources.entry(key.clone()).or_default();
+        resource.variants.retain(|item| item.vary != entry.vary);
+        resource.variants.push(entry);
+        let size = resource
+            .variants
+            .iter()
+            .map(|item| item.get_size())
+            .sum::<usize>();
+        self.size = self.size - resource.size + size;
+        resource.size = size;
+        self.touch(&key);
+        while self.size > max_size {
+            let Some((_, evicted_key)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(resource) = self.resources.remove(&evicted_key) {
+                self.size -= resource.size;
+            }
+        }
+    }
+}
+#[derive(Default)]
+struct HttpCacheInner {
+    partitions: HashMap<String, CachePartition>,
+    // The waiters of the requests which are sent to the upstream.
+    in_flight: HashMap<String, Vec<oneshot::Sender<()>>>,
+    applied_generation: u64,
+}
+enum Lookup {
+    Fresh(Response),
+    Stale(Response, HeaderMap<HeaderValue>),
+    Revalidate(HeaderMap<HeaderValue>),
+    Miss,
+}
+// The cache of one listener in one worker, it is shared by the connections of the worker.
+#[derive(Clone)]
+pub struct HttpCache {
+    port: i32,
+    purge_log: CachePurgeLog,
+    inner: Rc<RefCell<HttpCacheInner>>,
+}
+impl HttpCache {
+    pub fn new(port: i32, purge_log: CachePurgeLog) -> Self {
+        let applied_generation = purge_log.get_generation();
+        Self {
+            port,
+            purge_log,
+            inner: Rc::new(RefCell::new(HttpCacheInner {
+                applied_generation,
+                ..Default::default()
+            })),
+        }
+    }
+    // The unsafe methods invalidate the cached responses of the uri.
+    pub fn invalidate(&self, route_id: &str, parts: &Parts) -> Result<(), AppError> {
+        self.apply_purges()?;
+        if let Some(partition) = self.inner.borrow_mut().partitions.get_mut(route_id) {
+            partition.remove(&get_cache_key(parts));
+        }
+        Ok(())
+    }
+    // Only for the GET and HEAD requests, the fetch sends the request to the upstream with the
+    // extra conditional headers.
+    pub async fn send_request<F, Fut>(
+        &self,
+        cache_config: &CacheConfig,
+        route_id: &str,
+        parts: &Parts,
+        fetch: F,
+    ) -> Result<Response, AppError>
+    where
+        F: Fn(HeaderMap<HeaderValue>) -> Fut + 'static,
+        Fut: Future<Output = Result<Response, AppError>> + 'static,
+    {
+        self.apply_purges()?;
+        let key = get_cache_key(parts);
+        let request_directives = parse_cache_control(&parts.headers);
+        if request_directives.contains_key("no-store") {
+            return fetch(HeaderMap::new()).await;
+        }
+        let must_revalidate = request_directives.contains_key("no-cache")
+            || request_directives
+                .get("max-age")
+                .is_some_and(|item| item.as_deref() == Some("0"));
+        match self.lookup(route_id, &key, parts, must_revalidate) {
+            Lookup::Fresh(response) => return Ok(response),
+            Lookup::Stale(response, conditional_headers) => {
+                self.revalidate_in_background(
+                    cache_config.clone(),
+                    route_id.to_string(),
+                    key,
+                    parts,
+                    conditional_headers,
+                    fetch,
+                );
+                return Ok(response);
+            }
+            Lookup::Revalidate(conditional_headers) => {
+                let mut response = fetch(conditional_headers).await?;
+                // The entry may have been evicted while the request was sent.
+                if response.status() == StatusCode::NOT_MODIFIED {
+                    if let Some(response) = self.refresh(route_id, &key, parts, response.headers())
+                    {
+                        return Ok(response);
+                    }
+                    response = fetch(HeaderMap::new()).await?;
+                }
+                return self
+                    .on_response(cache_config, route_id, key, parts, response)
+                    .await;
+            }
+            Lookup::Miss => {}
+        }
+        // The head request is not stored, the get response is stored for both.
+        if parts.method == Method::HEAD {
+            let mut response = fetch(HeaderMap::new()).await?;
+            set_cache_status(response.headers_mut(), "MISS");
+            return Ok(response);
+        }
+        let in_flight_key = format!("{}|{}|{}", route_id, key.0, key.1);
+        let receiver_option = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.in_flight.get_mut(&in_flight_key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    inner.in_flight.insert(in_flight_key.clone(), vec![]);
+                    None
+                }
+            }
+        };
+        // The collapsed request uses the response stored by the first request, or goes to the
+        // upstream itself if the response was not cacheable.
+        if let Some(receiver) = receiver_option {
+            let _ = receiver.await;
+            if let Lookup::Fresh(response) = self.lookup(route_id, &key, parts, false) {
+                return Ok(response);
+            }
+            let mut response = fetch(HeaderMap::new()).await?;
+            set_cache_status(response.headers_mut(), "MISS");
+            return Ok(response);
+        }
+        let _in_flight_guard = InFlightGuard {
+            inner: self.inner.clone(),
+            key: in_flight_key,
+        };
+        let response = fetch(HeaderMap::new()).await?;
+        self.on_response(cache_config, route_id, key, parts, response)
+            .await
+    }
+    fn apply_purges(&self) -> Result<(), AppError> {
+        let generation = self.purge_log.get_generation();
+        let mut inner = self.inner.borrow_mut();
+        if generation == inner.applied_generation {
+            return Ok(());
+        }
+        match self.purge_log.get_purges_after(inner.applied_generation)? {
+            Some(purges) => {
+                for purge in purges
+                    .iter()
+                    .filter(|item| item.port.is_none_or(|port| port == self.port))
+                {
+                    for partition in inner.partitions.values_mut() {
+                        let purged_keys: Vec<CacheKey> = partition
+                            .resources
+                            .keys()
+                            .filter(|(_, path)| path.starts_with(&purge.prefix))
+                            .cloned()
+                            .collect();
+                        for key in purged_keys.iter() {
+                            partition.remove(key);
+                        }
+                    }
+                }
+            }
+            None => inner.partitions.clear(),
+        }
+        inner.applied_generation = generation;
+        Ok(())
+    }
+    fn lookup(
+        &self,
+        route_id: &str,
+        key: &CacheKey,
+        parts: &Parts,
+        must_revalidate: bool,
+    ) -> Lookup {
+        let mut inner = self.inner.borrow_mut();
+        let Some(partition) = inner.partitions.get_mut(route_id) else {
+            return Lookup::Miss;
+        };
+        let Some(entry) = partition.resources.get(key).and_then(|resource| {
+            resource
+                .variants
+                .iter()
+                .find(|item| item.is_vary_matched(&parts.headers))
+        }) else {
+            return Lookup::Miss;
+        };
+        let lookup = if !must_revalidate && entry.is_fresh() {
+            Lookup::Fresh(build_entry_response(entry, parts, "HIT"))
+        } else if !must_revalidate && entry.can_serve_stale() {
+            Lookup::Stale(
+                build_entry_response(entry, parts, "STALE"),
+                entry.get_conditional_headers(),
+            )
+        } else {
+            Lookup::Revalidate(entry.get_conditional_headers())
+        };
+        partition.touch(key);
+        lookup
+    }
+    // The not modified response refreshes the stored entry, the other responses replace it.
+    async fn on_response(
+        &self,
+        cache_config: &CacheConfig,
+        route_id: &str,
+        key: CacheKey,
+        parts: &Parts,
+        response: Response,
+    ) -> Result<Response, AppError> {
+        // The not modified response of the client's own conditional request is not stored.
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(self
+                .refresh(route_id, &key, parts, response.headers())
+                .unwrap_or(response));
+        }
+        let (mut response_parts, mut payload) = response.into_parts();
+        let body = match payload.next_data().await {
+            Some(data_result) => data_result.map_err(|e| AppError(e.to_string()))?,
+            None => Bytes::new(),
+        };
+        let entry_option = new_cache_entry(
+            cache_config,
+            parts,
+            response_parts.status,
+            &response_parts.headers,
+            &body,
+        );
+        match entry_option {
+            Some(entry) => {
+                self.inner
+                    .borrow_mut()
+                    .partitions
+                    .entry(route_id.to_string())
+                    .or_default()
+                    .insert(key, entry, cache_config.max_size);
+            }
+            // The stored response is not valid any more.
+            None => {
+                if let Some(partition) = self.inner.borrow_mut().partitions.get_mut(route_id) {
+                    partition.remove(&key);
+                }
+            }
+        }
+        set_cache_status(&mut response_parts.headers, "MISS");
+        Ok(Response::from_parts(
+            response_parts,
+            Payload::Fixed(FixedPayload::new(body)),
+        ))
+    }
+    fn refresh(
+        &self,
+        route_id: &str,
+        key: &CacheKey,
+        parts: &Parts,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<Response> {
+        let mut inner = self.inner.borrow_mut();
+        let partition = inner.partitions.get_mut(route_id)?;
+        let entry = partition
+            .resources
+            .get_mut(key)?
+            .variants
+            .iter_mut()
+            .find(|item| item.is_vary_matched(&parts.headers))?;
+        for (name, value) in headers.iter() {
+            entry.headers.insert(name, value.clone());
+        }
+        let directives = parse_cache_control(&entry.headers);
+        entry.stored_at = Instant::now();
+        entry.initial_age = get_initial_age(&entry.headers);
+        if let Some(fresh_lifetime) = get_fresh_lifetime(&directives, &entry.headers) {
+            entry.fresh_lifetime = fresh_lifetime;
+        }
+        Some(build_entry_response(entry, parts, "REVALIDATED"))
+    }
+    fn revalidate_in_background<F, Fut>(
+        &self,
+        cache_config: CacheConfig,
+        route_id: String,
+        key: CacheKey,
+        parts: &Parts,
+        conditional_headers: HeaderMap<HeaderValue>,
+        fetch: F,
+    ) where
+        F: Fn(HeaderMap<HeaderValue>) -> Fut + 'static,
+        Fut: Future<Output = Result<Response, AppError>> + 'static,
+    {
+        let in_flight_key = format!("{}|{}|{}", route_id, key.0, key.1);
+        {
+            let mut inner = self.inner.borrow_mut();
+            if inner.in_flight.contains_key(&in_flight_key) {
+                return;
+            }
+            inner.in_flight.insert(in_flight_key.clone(), vec![]);
+        }
+        let http_cache = self.clone();
+        let mut request_parts = http::Request::new(()).into_parts().0;
+        request_parts.method = parts.method.clone();
+        request_parts.uri = parts.uri.clone();
+        request_parts.headers = parts.headers.clone();
+        monoio::spawn(async move {
+            let _in_flight_guard = InFlightGuard {
+                inner: http_cache.inner.clone(),
+                key: in_flight_key,
+            };
+            let result = match fetch(conditional_headers).await {
+                Ok(response) => {
+                    http_cache
+                        .on_response(&cache_config, &route_id, key, &request_parts, response)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!("Revalidate the cached response error,{}", e);
+            }
+        });
+    }
+}
+// Wakes the collapsed requests when the upstream request is done or dropped.
+struct InFlightGuard {
+    inner: Rc<RefCell<HttpCacheInner>>,
+    key: String,
+}
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(waiters) = self.inner.borrow_mut().in_flight.remove(&self.key) {
+            for waiter in waiters {
+                let _ = waiter.send(());
+            }
+        }
+    }
+}
+fn get_cache_key(parts: &Parts) -> CacheKey {
+    let host = parts
+        .headers
+        .get(HOST)
+        .and_then(|item| item.to_str().ok())
+        .or(parts.uri.host())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map(|item| item.as_str())
+        .unwrap_or("/")
+        .to_string();
+    (host, path_and_query)
+}
+// The directive names are lowercased, the quotes of the values are removed.
+fn parse_cache_control(headers: &HeaderMap<HeaderValue>) -> HashMap<String, Option<String>> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .flat_map(|item| item.split(','))
+        .filter(|item| !item.trim().is_empty())
+        .map(|item| match item.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (item.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+fn get_seconds(directives: &HashMap<String, Option<String>>, name: &str) -> Option<Duration> {
+    directives
+        .get(name)?
+        .as_deref()?
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+// The s-maxage is used before the max-age and the Expires.
+fn get_fresh_lifetime(
+    directives: &HashMap<String, Option<String>>,
+    headers: &HeaderMap<HeaderValue>,
+) -> Option<Duration> {
+    if let Some(fresh_lifetime) =
+        get_seconds(directives, "s-maxage").or_else(|| get_seconds(directives, "max-age"))
+    {
+        return Some(fresh_lifetime);
+    }
+    let expires = headers.get(EXPIRES).and_then(|item| item.to_str().ok())?;
+    // The invalid Expires means the response is already expired.
+    let Ok(expires) = httpdate::parse_http_date(expires) else {
+        return Some(Duration::ZERO);
+    };
+    let date = headers
+        .get(DATE)
+        .and_then(|item| item.to_str().ok())
+        .and_then(|item| httpdate::parse_http_date(item).ok())
+        .unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(date).unwrap_or_default())
+}
+fn get_initial_age(headers: &HeaderMap<HeaderValue>) -> Duration {
+    headers
+        .get(AGE)
+        .and_then(|item| item.to_str().ok())
+        .and_then(|item| item.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
+// Returns None if the response must not be stored.
+fn new_cache_entry(
+    cache_config: &CacheConfig,
+    parts: &Parts,
+    status: StatusCode,
+    headers: &HeaderMap<HeaderValue>,
+    body: &Bytes,
+) -> Option<CacheEntry> {
+    if parts.method != Method::GET
+        || !CACHEABLE_STATUSES.contains(&status.as_u16())
+        || headers.contains_key(SET_COOKIE)
+        || body.len() > cache_config.max_entry_size
+    {
+        return None;
+    }
+    let directives = parse_cache_control(headers);
+    if directives.contains_key("no-store") || directives.contains_key("private") {
+        return None;
+    }
+    if parts.headers.contains_key(AUTHORIZATION)
+        && !["public", "s-maxage", "must-revalidate"]
+            .iter()
+            .any(|item| directives.contains_key(*item))
+    {
+        return None;
+    }
+    let mut vary = vec![];
+    for name in headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .flat_map(|item| item.split(','))
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+    {
+        if name == "*" {
+            return None;
+        }
+        let name = HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes()).ok()?;
+        let value = parts.headers.get(&name).cloned();
+        vary.push((name, value));
+    }
+    // The no-cache response is stored only if it can be revalidated.
+    let has_validator = headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED);
+    let fresh_lifetime = if directives.contains_key("no-cache") {
+        if !has_validator {
+            return None;
+        }
+        Duration::ZERO
+    } else {
+        get_fresh_lifetime(&directives, headers)
+            .or(cache_config.default_ttl_seconds.map(Duration::from_secs))?
+    };
+    if fresh_lifetime.is_zero() && !has_validator {
+        return None;
+    }
+    let stale_while_revalidate = get_seconds(&directives, "stale-while-revalidate").unwrap_or(
+        Duration::from_secs(cache_config.stale_while_revalidate_seconds),
+    );
+    let must_revalidate = ["no-cache", "must-revalidate", "proxy-revalidate"]
+        .iter()
+        .any(|item| directives.contains_key(*item));
+    let mut headers = headers.clone();
+    headers.remove(CACHE_STATUS_HEADER);
+    Some(CacheEntry {
+        vary,
+        status,
+        initial_age: get_initial_age(&headers),
+        headers,
+        body: body.clone(),
+        stored_at: Instant::now(),
+        fresh_lifetime,
+        stale_while_revalidate,
+        must_revalidate,
+    })
+}
+// The conditional request of the client is answered by the entry.
+fn build_entry_response(entry: &CacheEntry, parts: &Parts, cache_status: &'static str) -> Response {
+    let mut headers = entry.headers.clone();
+    headers.insert(AGE, HeaderValue::from(entry.get_age().as_secs()));
+    set_cache_status(&mut headers, cache_status);
+    let if_none_match = parts
+        .headers
+        .get(IF_NONE_MATCH)
+        .and_then(|item| item.to_str().ok());
+    let etag = entry.headers.get(ETAG).and_then(|item| item.to_str().ok());
+    let is_not_modified = match (if_none_match, etag) {
+        (Some(if_none_match), Some(etag)) => if_none_match
+            .split(',')
+            .map(|item| item.trim().trim_start_matches("W/"))
+            .any(|item| item == "*" || item == etag.trim_start_matches("W/")),
+        _ => false,
+    };
+    let (status, payload) = if is_not_modified {
+        (StatusCode::NOT_MODIFIED, Payload::None)
+    } else if parts.method == Method::HEAD {
+        (entry.status, Payload::None)
+    } else {
+        (
+            entry.status,
+            Payload::Fixed(FixedPayload::new(entry.body.clone())),
+        )
+    };
+    let mut response = Response::builder().status(status).body(payload).unwrap();
+    *response.headers_mut() = headers;
+    response
+}
+fn set_cache_status(headers: &mut HeaderMap<HeaderValue>, cache_status: &'static str) {
+    headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static(cache_status));
+}
@@ -1,4 +1,8 @@
+use crate::constants::common_constants::ACCESS_LOG_TARGET;
+use crate::constants::common_constants::BLOCKING_THREAD_COUNT;
+use crate::constants::common_constants::MAX_REQUEST_HEADER_COUNT;
 use crate::middleware::log_service::LogService;
+use crate::middleware::route_service::build_violation_response;
 use crate::vojo::app_error::AppError;
 use crate::vojo::handler::Handler;
 use crate::vojo::thread_local_info::ThreadLocalInfo;
@@ -21,7 +25,10 @@ use monoio::{
 use monoio_http::{
     common::{error::HttpError, request::Request, response::Response},
     h1::{
-        codec::{decoder::RequestDecoder, encoder::GenericEncoder},
+        codec::{
+            decoder::{FillPayload, RequestDecoder},
+            encoder::GenericEncoder,
+        },
         payload::{FixedPayload, Payload},
     },
     util::spsc::{spsc_pair, SPSCReceiver},
@@ -34,14 +41,28 @@ use tower::{service_fn, BoxError, Service, ServiceExt};
 use crate::control_plane::rest_api::start_control_plane;
 use crate::middleware::ip_allow_service::IpAllowService;
 use crate::middleware::route_service::handle_request;
+use crate::proxy::http_cache::HttpCache;
+use crate::proxy::proxy_protocol::accept_proxy_protocol;
+use crate::proxy::tcp_proxy::handle_tcp_connection;
+use crate::proxy::upstream_pool::UpstreamPool;
+use crate::vojo::app_config::ProxyProtocolConfig;
+use crate::vojo::app_config::ServiceType;
 use crate::vojo::gateway_request;
 use crate::vojo::gateway_request::GatewayRequest;
+use crate::vojo::size_limits::SizeLimitViolation;
+use crate::vojo::size_limits::SizeLimits;
 use crossbeam::channel::{bounded, select};
 use futures::channel::mpsc::unbounded;
 use futures::channel::oneshot::channel;
+use monoio::blocking::DefaultThreadPool;
+use monoio::buf::{IoBufMut, IoVecBufMut, IoVecWrapperMut};
 use monoio::io::Canceller;
-use monoio_http_client::Client;
+use monoio::BufResult;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::net::SocketAddr;
 use std::pin::pin;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
@@ -51,16 +72,18 @@ pub fn create_monoio_runtime(port: i32, handler: Handler) {
 
     let mut handler_write_lock = lock.senders.lock().unwrap();
 
-    let first_key = handler_write_lock.keys().cloned().next().unwrap();
-    let removed_list = handler_write_lock.remove(&first_key).unwrap();
+    let removed_list = handler_write_lock.remove(&port).unwrap_or_default();
     drop(handler_write_lock);
     for item in removed_list {
-        item.send(1).unwrap();
+        let _ = item.send(1);
     }
     let cpus = num_cpus::get();
     println!("Cpu core is {}", cpus);
+    // The blocking file lookups of the workers share the pool.
+    let thread_pool = DefaultThreadPool::new(BLOCKING_THREAD_COUNT);
     for i in 0..cpus {
         let handle_clone1 = handler.clone();
+        let thread_pool = thread_pool.clone();
 
         println!("thread is {}", i);
         std::thread::spawn(move || {
@@ -75,6 +98,7 @@ pub fn create_monoio_runtime(port: i32, handler: Handler) {
             let mut rt = monoio::RuntimeBuilder::<monoio::IoUringDriver>::new()
                 .with_entries(256)
                 .enable_timer()
+                .attach_thread_pool(Box::new(thread_pool))
                 .build()
                 .unwrap();
             rt.block_on(async { main_with_error(port, handle_clone1).await });
@@ -88,17 +112,20 @@ pub async fn main_with_error(port: i32, handler: Handler) {
     let _ = tracing::subscriber::set_global_default(subscriber);
     let addr = format!("0.0.0.0:{port}");
     let listener = TcpListener::bind(addr.clone()).unwrap();
-    let client = Client::default();
+    let upstream_pool = UpstreamPool::default();
+    upstream_pool.start_sweep_task();
+    let http_cache = HttpCache::new(port, handler.cache_purge_log.clone());
     let thread_local_infos = Arc::new(Mutex::new(ThreadLocalInfo::new()));
     info!("Listening {}", addr);
     loop {
         if let Ok((stream, addr)) = listener.accept().await {
             monoio::spawn(handle_connection(
                 port,
-                client.clone(),
+                upstream_pool.clone(),
+                http_cache.clone(),
                 handler.clone(),
                 stream,
-                addr.to_string(),
+                addr,
                 thread_local_infos.clone(),
             ));
         }
@@ -106,34 +133,98 @@ pub async fn main_with_error(port: i32, handler: Handler) {
 }
 async fn handle_connection(
     port: i32,
-    client: Client,
+    upstream_pool: UpstreamPool,
+    http_cache: HttpCache,
     handler: Handler,
     stream: TcpStream,
-    addr: String,
+    peer_addr: SocketAddr,
     thread_local_info_mutex: Arc<Mutex<ThreadLocalInfo>>,
 ) {
+    let (proxy_protocol, server_type, size_limits) = match get_listener_config(&handler, port) {
+        Ok(listener_config) => listener_config,
+        Err(e) => {
+            error!("Get the listener config error,{}", e);
+            return;
+        }
+    };
+    let local_addr = match stream.local_addr() {
+        Ok(local_addr) => local_addr,
+        Err(e) => {
+            error!("Get the local address error,{}", e);
+            return;
+        }
+    };
+    let (stream, client_addr) =
+        match accept_proxy_protocol(stream, peer_addr, proxy_protocol.as_ref()).await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Accept the proxy protocol error,{}", e);
+                return;
+            }
+        };
+    if server_type == ServiceType::Tcp {
+        if let Err(e) = handle_tcp_connection(port, handler, stream, client_addr, local_addr).await
+        {
+            error!("Handle the tcp connection error,{}", e);
+        }
+        return;
+    }
+    let addr = client_addr.ip().to_string();
     let (r, w) = stream.into_split();
     let sender = GenericEncoder::new(w);
-    let mut receiver = RequestDecoder::new(r);
+    let closing = Rc::new(Cell::new(false));
+    let head_limit = Rc::new(RefCell::new(HeadLimit::new(size_limits.as_ref())));
+    let mut receiver = RequestDecoder::new(ClosableRead {
+        inner: r,
+        closing: closing.clone(),
+        head_limit: head_limit.clone(),
+    });
     let (mut tx, rx) = spsc_pair();
     monoio::spawn(handle_task(
         port,
-        client,
+        upstream_pool,
+        http_cache,
         handler,
         rx,
         sender,
-        addr,
+        addr.clone(),
         thread_local_info_mutex,
+        closing.clone(),
+        head_limit.clone(),
     ));
 
     loop {
-        match receiver.next().await {
+        // The body of the previous request is read first, so only the next head is limited.
+        let next = match receiver.fill_payload().await {
+            Ok(_) => {
+                head_limit.borrow_mut().start();
+                receiver.next().await
+            }
+            Err(e) => Some(Err(e)),
+        };
+        // The request handler answers the head which is over the limit after the earlier requests.
+        let violation = head_limit.borrow().violation;
+        if let Some(violation) = violation {
+            info!(
+                target: ACCESS_LOG_TARGET,
+                "{} - - - reason={}", addr, violation
+            );
+            return;
+        }
+        match next {
             None => {
                 println!("connection closed, connection handler exit");
                 return;
             }
-            Some(Err(_)) => {
-                println!("receive request failed, connection handler exit");
+            // The head which is rejected by the decoder has no response.
+            Some(Err(_)) if closing.get() => {
+                return;
+            }
+            Some(Err(e)) => {
+                info!(
+                    target: ACCESS_LOG_TARGET,
+                    "{} - - - reason=invalid_request error={}", addr, e
+                );
                 return;
             }
             Some(Ok(item)) => match tx.send(item).await {
@@ -149,15 +240,38 @@ async fn handle_connection(
     }
 }
 
+fn get_listener_config(
+    handler: &Handler,
+    port: i32,
+) -> Result<(Option<ProxyProtocolConfig>, ServiceType, Option<SizeLimits>), AppError> {
+    let app_config = handler
+        .shared_app_config
+        .read()
+        .map_err(|e| AppError(e.to_string()))?;
+    let service_config = &app_config
+        .api_service_config
+        .get(&port)
+        .ok_or(AppError(format!("Can not find port {} in config.", port)))?
+        .service_config;
+    Ok((
+        service_config.proxy_protocol.clone(),
+        service_config.server_type.clone(),
+        service_config.size_limits.clone(),
+    ))
+}
+#[allow(clippy::too_many_arguments)]
 async fn handle_task(
     port: i32,
 
-    client: Client,
+    upstream_pool: UpstreamPool,
+    http_cache: HttpCache,
     handler: Handler,
     mut receiver: SPSCReceiver<Request>,
     mut sender: impl Sink<Response, Error = impl Into<HttpError>>,
     remote_addr: String,
     thread_local_info_mutex: Arc<Mutex<ThreadLocalInfo>>,
+    closing: Rc<Cell<bool>>,
+    head_limit: Rc<RefCell<HeadLimit>>,
 ) -> Result<(), AppError> {
     let service_fn = service_fn(handle_request);
     let log_service_fn = layer_fn(|service| LogService {
@@ -177,6 +291,14 @@ async fn handle_task(
         let request = match receiver.recv().await {
             Some(r) => r,
             None => {
+                let violation = head_limit.borrow().violation;
+                if let Some(violation) = violation {
+                    sender
+                        .send_and_flush(build_violation_response(violation))
+                        .await
+                        .map_err(Into::into)
+                        .map_err(|e| AppError(e.to_string()))?;
+                }
                 return Ok(());
             }
         };
@@ -185,22 +307,130 @@ async fn handle_task(
             port,
             request,
             remote_addr.clone(),
-            client.clone(),
+            upstream_pool.clone(),
+            http_cache.clone(),
             handler.clone(),
             thread_local_info_mutex.clone(),
         );
 
-        let resp = tower_service.call(gateway_request).await;
-
-        match resp {
-            Ok(s) => sender
-                .send_and_flush(s)
-                .await
-                .map_err(Into::into)
-                .map_err(|e| AppError(e.to_string()))?,
+        // The errors of the middlewares are answered too, the connection is kept in order.
+        let resp = match tower_service.call(gateway_request).await {
+            Ok(resp) => resp,
             Err(e) => {
                 error!("{}", e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Payload::Fixed(FixedPayload::new(Bytes::from(
+                        e.to_string(),
+                    ))))
+                    .map_err(|e| AppError(e.to_string()))?
+            }
+        };
+        // The rest of the rejected request is never read, so the connection is closed.
+        let is_rejected = resp.extensions().get::<SizeLimitViolation>().is_some();
+        if is_rejected {
+            closing.set(true);
+        }
+        sender
+            .send_and_flush(resp)
+            .await
+            .map_err(Into::into)
+            .map_err(|e| AppError(e.to_string()))?;
+        if is_rejected {
+            return Ok(());
+        }
+    }
+}
+// Reads the end of the stream once the connection is closing, so the decoder stops buffering the
+// body of the rejected request. The head is limited while it is read, the decoder buffers the whole
+// head before the limits could be checked.
+struct ClosableRead<R> {
+    inner: R,
+    closing: Rc<Cell<bool>>,
+    head_limit: Rc<RefCell<HeadLimit>>,
+}
+impl<R: AsyncReadRent> AsyncReadRent for ClosableRead<R> {
+    async fn read<T: IoBufMut>(&mut self, buf: T) -> BufResult<usize, T> {
+        if self.closing.get() {
+            return (Ok(0), buf);
+        }
+        let (result, mut buf) = self.inner.read(buf).await;
+        if let Ok(n) = result {
+            // The read bytes start at the write pointer of the buffer.
+            let data = unsafe { std::slice::from_raw_parts(buf.write_ptr(), n) };
+            if self.head_limit.borrow_mut().check(data).is_some() {
+                self.closing.set(true);
+                return (Ok(0), buf);
+            }
+        }
+        (result, buf)
+    }
+    async fn readv<T: IoVecBufMut>(&mut self, mut buf: T) -> BufResult<usize, T> {
+        let slice = match IoVecWrapperMut::new(buf) {
+            Ok(slice) => slice,
+            Err(buf) => return (Ok(0), buf),
+        };
+        let (result, slice) = self.read(slice).await;
+        buf = slice.into_inner();
+        if let Ok(n) = result {
+            unsafe { buf.set_init(n) };
+        }
+        (result, buf)
+    }
+}
+// The limits of the listener, the routes are matched after the head is decoded so their limits are
+// checked with the decoded head. The head is counted as the size limits count it, without the
+// empty line. The part of the next head which was read with the previous request is not counted.
+#[derive(Debug, Default)]
+struct HeadLimit {
+    max_bytes: Option<usize>,
+    max_count: usize,
+    is_reading: bool,
+    lines: usize,
+    bytes: usize,
+    last_bytes: [u8; 2],
+    violation: Option<SizeLimitViolation>,
+}
+impl HeadLimit {
+    fn new(size_limits: Option<&SizeLimits>) -> Self {
+        HeadLimit {
+            max_bytes: size_limits.and_then(|item| item.max_header_bytes),
+            // The decoder rejects more headers without a response.
+            max_count: size_limits
+                .and_then(|item| item.max_header_count)
+                .unwrap_or(MAX_REQUEST_HEADER_COUNT)
+                .min(MAX_REQUEST_HEADER_COUNT),
+            ..Default::default()
+        }
+    }
+    fn start(&mut self) {
+        self.is_reading = true;
+        self.lines = 0;
+        self.bytes = 0;
+        self.last_bytes = [0; 2];
+    }
+    // The head ends with an empty line, the line may end with a bare line feed.
+    fn check(&mut self, data: &[u8]) -> Option<SizeLimitViolation> {
+        if !self.is_reading {
+            return None;
+        }
+        for item in data {
+            self.bytes += 1;
+            if *item == b'\n' {
+                if self.lines > 0 && (self.last_bytes[1] == b'\n' || self.last_bytes == *b"\n\r") {
+                    self.bytes -= if self.last_bytes[1] == b'\r' { 2 } else { 1 };
+                    self.is_reading = false;
+                    break;
+                }
+                self.lines += 1;
             }
+            self.last_bytes = [self.last_bytes[1], *item];
+        }
+        if self.lines.saturating_sub(1) > self.max_count {
+            self.violation = Some(SizeLimitViolation::TooManyHeaders);
+        } else if self.max_bytes.is_some_and(|max| self.bytes > max) {
+            self.violation = Some(SizeLimitViolation::HeaderTooLarge);
         }
+        self.violation
     }
 }
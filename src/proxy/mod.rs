@@ -1 +1,6 @@
+pub mod http_cache;
 pub mod http_proxy;
+pub mod proxy_protocol;
+pub mod static_file;
+pub mod tcp_proxy;
+pub mod upstream_pool;
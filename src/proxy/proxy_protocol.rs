@@ -0,0 +1,366 @@
+use crate::constants::common_constants::DEFAULT_PROXY_PROTOCOL_TIMEOUT_MILLIS;
+use crate::constants::common_constants::MAX_PROXY_PROTOCOL_HEADER_SIZE;
+use crate::vojo::allow_deny_ip::ip_is_in_cidrs;
+use crate::vojo::app_config::ProxyProtocolConfig;
+use crate::vojo::app_config::ProxyProtocolVersion;
+use crate::vojo::app_error::AppError;
+use monoio::io::AsyncReadRent;
+use monoio::io::PrefixedReadIo;
+use monoio::net::TcpStream;
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+
+// The bytes read after the header are given back to the connection.
+pub type PrefixedStream = PrefixedReadIo<TcpStream, Cursor<Vec<u8>>>;
+
+// Returns the stream and the client address, the header is only read from the trusted sources.
+pub async fn accept_proxy_protocol(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    proxy_protocol_option: Option<&ProxyProtocolConfig>,
+) -> Result<(PrefixedStream, SocketAddr), AppError> {
+    let Some(proxy_protocol) = proxy_protocol_option else {
+        return Ok((PrefixedReadIo::new(stream, Cursor::new(vec![])), peer_addr));
+    };
+    if !ip_is_in_cidrs(&peer_addr.ip(), &proxy_protocol.trusted_sources) {
+        return Ok((PrefixedReadIo::new(stream, Cursor::new(vec![])), peer_addr));
+    }
+    let timeout = Duration::from_millis(DEFAULT_PROXY_PROTOCOL_TIMEOUT_MILLIS);
+    let (source_addr_option, rest) = monoio::time::timeout(timeout, read_header(&mut stream))
+        .await
+        .map_err(|_| AppError(String::from("Read the proxy protocol header timed out")))??;
+    let client_addr = source_addr_option.unwrap_or(peer_addr);
+    Ok((PrefixedReadIo::new(stream, Cursor::new(rest)), client_addr))
+}
+async fn read_header(stream: &mut TcpStream) -> Result<(Option<SocketAddr>, Vec<u8>), AppError> {
+    let mut data = vec![];
+    loop {
+        let (read_result, buf) = stream.read(Vec::with_capacity(512)).await;
+        let read_size = read_result.map_err(|e| AppError(e.to_string()))?;
+        if read_size == 0 {
+            return Err(AppError(String::from(
+                "The connection closed before the proxy protocol header",
+            )));
+        }
+        data.extend_from_slice(&buf);
+        if let Some((source_addr_option, header_length)) = parse_header(&data)? {
+            return Ok((source_addr_option, data.split_off(header_length)));
+        }
+        if data.len() > MAX_PROXY_PROTOCOL_HEADER_SIZE {
+            return Err(AppError(String::from(
+                "The proxy protocol header is too long",
+            )));
+        }
+    }
+}
+// Returns None if more data is needed, the source address is None for the local connections.
+fn parse_header(data: &[u8]) -> Result<Option<(Option<SocketAddr>, usize)>, AppError> {
+    let compared_length = data.len().min(V2_SIGNATURE.len());
+    if data[..compared_length] == V2_SIGNATURE[..compared_length] {
+        return parse_v2_header(data);
+    }
+    let compared_length = data.len().min(V1_PREFIX.len());
+    if data[..compared_length] == V1_PREFIX[..compared_length] {
+        return parse_v1_header(data);
+    }
+    Err(AppError(String::from("Invalid proxy protocol header")))
+}
+fn parse_v1_header(data: &[u8]) -> Result<Option<(Option<SocketAddr>, usize)>, AppError> {
+    let Some(line_end) = data.windows(2).position(|item| item == b"\r\n") else {
+        if data.len() >= V1_MAX_LENGTH {
+            return Err(AppError(String::from(
+                "The proxy protocol v1 header is too long",
+            )));
+        }
+        return Ok(None);
+    };
+    let line = std::str::from_utf8(&data[..line_end]).map_err(|e| AppError(e.to_string()))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let source_addr_option = match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4" | "TCP6", source_ip, _, source_port, _] => {
+            let ip = source_ip
+                .parse::<IpAddr>()
+                .map_err(|e| AppError(e.to_string()))?;
+            let port = source_port
+                .parse::<u16>()
+                .map_err(|e| AppError(e.to_string()))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => {
+            return Err(AppError(format!(
+                "Invalid proxy protocol v1 header,{}",
+                line
+            )))
+        }
+    };
+    Ok(Some((source_addr_option, line_end + 2)))
+}
+fn parse_v2_header(data: &[u8]) -> Result<Option<(Option<SocketAddr>, usize)>, AppError> {
+    if data.len() < V2_HEADER_LENGTH {
+        return Ok(None);
+    }
+    let version_command = data[12];
+    if version_command >> 4 != 2 {
+        return Err(AppError(String::from("Invalid proxy protocol v2 version")));
+    }
+    let header_length = V2_HEADER_LENGTH + u16::from_be_bytes([data[14], data[15]]) as usize;
+    // Rejected before the addresses are read.
+    if header_length > MAX_PROXY_PROTOCOL_HEADER_SIZE {
+        return Err(AppError(String::from(
+            "The proxy protocol v2 header is too long",
+        )));
+    }
+    if data.len() < header_length {
+        return Ok(None);
+    }
+    // The local command is sent by the proxy itself, e.g. the health checks.
+    if version_command & 0x0f == 0 {
+        return Ok(Some((None, header_length)));
+    }
+    let addresses = &data[V2_HEADER_LENGTH..header_length];
+    let source_addr_option = match data[13] >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        2 if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        // The unix sockets and the unspecified families keep the address of the connection.
+        _ => None,
+    };
+    Ok(Some((source_addr_option, header_length)))
+}
+pub fn encode_header(
+    version: &ProxyProtocolVersion,
+    source_addr: SocketAddr,
+    destination_addr: SocketAddr,
+) -> Vec<u8> {
+    // The source and the destination must be in the same family.
+    let (source_addr, destination_addr) = match (source_addr, destination_addr) {
+        (SocketAddr::V4(source), SocketAddr::V6(destination)) => (
+            SocketAddr::new(IpAddr::V6(source.ip().to_ipv6_mapped()), source.port()),
+            SocketAddr::V6(destination),
+        ),
+        (SocketAddr::V6(source), SocketAddr::V4(destination)) => (
+            SocketAddr::V6(source),
+            SocketAddr::new(
+                IpAddr::V6(destination.ip().to_ipv6_mapped()),
+                destination.port(),
+            ),
+        ),
+        addrs => addrs,
+    };
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = if source_addr.is_ipv4() {
+                "TCP4"
+            } else {
+                "TCP6"
+            };
+            format!(
+                "PROXY {} {} {} {} {}\r\n",
+                family,
+                source_addr.ip(),
+                destination_addr.ip(),
+                source_addr.port(),
+                destination_addr.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.push(0x21);
+            let mut addresses = vec![];
+            match (source_addr.ip(), destination_addr.ip()) {
+                (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                    header.push(0x11);
+                    addresses.extend_from_slice(&source_ip.octets());
+                    addresses.extend_from_slice(&destination_ip.octets());
+                }
+                (source_ip, destination_ip) => {
+                    header.push(0x21);
+                    addresses.extend_from_slice(&get_ipv6(source_ip).octets());
+                    addresses.extend_from_slice(&get_ipv6(destination_ip).octets());
+                }
+            }
+            addresses.extend_from_slice(&source_addr.port().to_be_bytes());
+            addresses.extend_from_slice(&destination_addr.port().to_be_bytes());
+            header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+            header.extend_from_slice(&addresses);
+            header
+        }
+    }
+}
+fn get_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monoio::io::AsyncWriteRentExt;
+    use monoio::net::TcpListener;
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+    // Writes the chunks one by one, so the header is read in several reads.
+    async fn read_header_from_chunks(
+        chunks: Vec<Vec<u8>>,
+    ) -> Result<(Option<SocketAddr>, Vec<u8>), AppError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let writer = monoio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            for chunk in chunks {
+                let (write_result, _) = stream.write_all(chunk).await;
+                write_result.unwrap();
+                monoio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let result = read_header(&mut stream).await;
+        writer.await;
+        result
+    }
+
+    #[test]
+    fn test_parse_v1_tcp4() {
+        let data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+        let (source_addr, header_length) = parse_header(data).unwrap().unwrap();
+        assert_eq!(source_addr, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(&data[header_length..], b"GET /");
+    }
+    #[test]
+    fn test_parse_v1_tcp6() {
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n";
+        let (source_addr, header_length) = parse_header(data).unwrap().unwrap();
+        assert_eq!(source_addr, Some("[2001:db8::1]:4000".parse().unwrap()));
+        assert_eq!(header_length, data.len());
+    }
+    #[test]
+    fn test_parse_v1_unknown() {
+        let data = b"PROXY UNKNOWN\r\n";
+        assert_eq!(parse_header(data).unwrap(), Some((None, data.len())));
+        let data = b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n";
+        assert_eq!(parse_header(data).unwrap(), Some((None, data.len())));
+    }
+    #[test]
+    fn test_parse_v1_invalid() {
+        assert!(parse_header(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n").is_err());
+        assert!(parse_header(b"PROXY TCP4 192.168.0.1 192.168.0.11 70000 443\r\n").is_err());
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+    }
+    #[test]
+    fn test_parse_v2_ipv4() {
+        let header = v2_header(1, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x01, 0xbb]);
+        let (source_addr, header_length) = parse_header(&header).unwrap().unwrap();
+        assert_eq!(source_addr, Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(header_length, header.len());
+    }
+    #[test]
+    fn test_parse_v2_ipv6() {
+        let source_ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination_ip: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut addresses = source_ip.octets().to_vec();
+        addresses.extend_from_slice(&destination_ip.octets());
+        addresses.extend_from_slice(&[0x1f, 0x90, 0x01, 0xbb]);
+        let header = v2_header(1, 0x21, &addresses);
+        let (source_addr, header_length) = parse_header(&header).unwrap().unwrap();
+        assert_eq!(source_addr, Some("[2001:db8::1]:8080".parse().unwrap()));
+        assert_eq!(header_length, header.len());
+    }
+    #[test]
+    fn test_parse_v2_local() {
+        let header = v2_header(0, 0x00, &[]);
+        assert_eq!(
+            parse_header(&header).unwrap(),
+            Some((None, V2_HEADER_LENGTH))
+        );
+        // The addresses of the local command are skipped.
+        let header = v2_header(0, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0, 1, 0, 2]);
+        assert_eq!(parse_header(&header).unwrap(), Some((None, header.len())));
+    }
+    #[test]
+    fn test_parse_v2_invalid_version() {
+        let mut header = v2_header(1, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0, 1, 0, 2]);
+        header[12] = 0x11;
+        assert!(parse_header(&header).is_err());
+    }
+    #[test]
+    fn test_parse_partial_header() {
+        let v1_header = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n";
+        let v2_header = v2_header(1, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x01, 0xbb]);
+        for header in [&v1_header[..], &v2_header[..]] {
+            for length in 1..header.len() {
+                assert_eq!(parse_header(&header[..length]).unwrap(), None);
+            }
+        }
+    }
+    #[test]
+    fn test_parse_oversized_v1_header() {
+        let mut data = b"PROXY TCP4 ".to_vec();
+        data.resize(V1_MAX_LENGTH, b'1');
+        assert!(parse_header(&data).is_err());
+    }
+    #[test]
+    fn test_parse_oversized_v2_header() {
+        let header = v2_header(1, 0x11, &[0; MAX_PROXY_PROTOCOL_HEADER_SIZE]);
+        assert!(parse_header(&header[..V2_HEADER_LENGTH]).is_err());
+    }
+    #[monoio::test(enable_timer = true)]
+    async fn test_read_split_v1_header() {
+        let chunks = vec![
+            b"PROXY TC".to_vec(),
+            b"P4 192.168.0.1 192.168.0".to_vec(),
+            b".11 56324 443\r".to_vec(),
+            b"\nGET / HTTP/1.1\r\n".to_vec(),
+        ];
+        let (source_addr, rest) = read_header_from_chunks(chunks).await.unwrap();
+        assert_eq!(source_addr, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+    #[monoio::test(enable_timer = true)]
+    async fn test_read_split_v2_header() {
+        let mut data = v2_header(1, 0x11, &[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x01, 0xbb]);
+        data.extend_from_slice(b"GET /");
+        let chunks = vec![
+            data[..5].to_vec(),
+            data[5..20].to_vec(),
+            data[20..].to_vec(),
+        ];
+        let (source_addr, rest) = read_header_from_chunks(chunks).await.unwrap();
+        assert_eq!(source_addr, Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+    #[monoio::test(enable_timer = true)]
+    async fn test_read_oversized_v2_header() {
+        let data = v2_header(1, 0x11, &vec![0; MAX_PROXY_PROTOCOL_HEADER_SIZE]);
+        let chunks = data.chunks(1024).map(|item| item.to_vec()).collect();
+        assert!(read_header_from_chunks(chunks).await.is_err());
+    }
+    #[monoio::test(enable_timer = true)]
+    async fn test_read_closed_before_header() {
+        let chunks = vec![b"PROXY TCP4 192.168".to_vec()];
+        assert!(read_header_from_chunks(chunks).await.is_err());
+    }
+}
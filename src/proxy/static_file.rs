@@ -0,0 +1,311 @@
+use crate::constants::common_constants::DEFAULT_INDEX_FILE;
+use crate::constants::common_constants::DEFAULT_TRY_FILES;
+use crate::constants::common_constants::STATIC_FILE_CHUNK_SIZE;
+use crate::constants::common_constants::STATIC_FILE_MAX_PENDING_CHUNKS;
+use crate::vojo::app_error::AppError;
+use crate::vojo::route::BaseRoute;
+use bytes::Bytes;
+use futures::task::AtomicWaker;
+use http::header::{
+    ACCEPT_RANGES, ALLOW, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use http::request::Parts;
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use monoio::fs::File;
+use monoio_http::common::response::Response;
+use monoio_http::h1::payload::{stream_payload_pair, FixedPayload, Payload, StreamPayloadSender};
+use std::fs::Metadata;
+use std::future::poll_fn;
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The endpoint is the root directory, the try_file is an nginx style list e.g.
+// "$uri $uri/ /index.html", a directory is served with its index file and the last item may be a
+// status like "=404". The root is the canonical root from the route table.
+pub async fn send_static_file(
+    root: &Path,
+    base_route: &BaseRoute,
+    path: &str,
+    parts: &Parts,
+) -> Result<Response, AppError> {
+    if parts.method != Method::GET && parts.method != Method::HEAD {
+        let mut response = build_response(StatusCode::METHOD_NOT_ALLOWED, Payload::None);
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return Ok(response);
+    }
+    let path = path.split_once('?').map(|item| item.0).unwrap_or(path);
+    let Some(path) = decode_path(path) else {
+        return Ok(build_response(StatusCode::BAD_REQUEST, Payload::None));
+    };
+    let try_files = match base_route.try_file.as_deref() {
+        Some(try_file) if !try_file.trim().is_empty() => try_file,
+        _ => DEFAULT_TRY_FILES,
+    };
+    let items: Vec<&str> = try_files.split_whitespace().collect();
+    for (index, item) in items.iter().enumerate() {
+        if let (true, Some(status)) = (index == items.len() - 1, item.strip_prefix('=')) {
+            let status = status
+                .parse::<u16>()
+                .ok()
+                .and_then(|item| StatusCode::from_u16(item).ok())
+                .ok_or(AppError(format!("Invalid try_file status {}", item)))?;
+            return Ok(build_response(status, Payload::None));
+        }
+        let candidate = item.replace("$uri", &path);
+        if let Some(file_path) = resolve_file(root, candidate).await? {
+            return send_file(&file_path, &parts.method, &parts.headers).await;
+        }
+    }
+    Ok(build_response(StatusCode::NOT_FOUND, Payload::None))
+}
+// The root is resolved once when the route table is built, not on every request.
+pub fn get_static_root(endpoint: &str) -> Result<PathBuf, AppError> {
+    let root = endpoint.strip_prefix("file://").unwrap_or(endpoint);
+    Path::new(root)
+        .canonicalize()
+        .map_err(|e| AppError(format!("The root {} is not found,{}", root, e)))
+}
+// Returns None if the path contains a parent segment or could not be decoded.
+fn decode_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            let high = (bytes[index + 1] as char).to_digit(16)?;
+            let low = (bytes[index + 2] as char).to_digit(16)?;
+            decoded.push((high * 16 + low) as u8);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    let decoded = String::from_utf8(decoded).ok()?;
+    let is_safe =
+        !decoded.contains('\0') && decoded.split(['/', '\\']).all(|segment| segment != "..");
+    is_safe.then_some(decoded)
+}
+// The file must be inside the root after the symlinks are resolved. The path lookups may block on
+// the disk, so they run on the blocking pool of the runtime.
+async fn resolve_file(root: &Path, candidate: String) -> Result<Option<PathBuf>, AppError> {
+    let root = root.to_path_buf();
+    monoio::spawn_blocking(move || {
+        let mut file_path = root.join(candidate.trim_start_matches('/'));
+        if candidate.ends_with('/') || file_path.is_dir() {
+            file_path = file_path.join(DEFAULT_INDEX_FILE);
+        }
+        let file_path = file_path.canonicalize().ok()?;
+        (file_path.starts_with(&root) && file_path.is_file()).then_some(file_path)
+    })
+    .await
+    .map_err(|e| AppError(format!("The file lookup failed,{:?}", e)))
+}
+async fn send_file(
+    file_path: &Path,
+    method: &Method,
+    headers: &HeaderMap<HeaderValue>,
+) -> Result<Response, AppError> {
+    let file = File::open(file_path)
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    let metadata = get_metadata(&file)?;
+    let file_size = metadata.len();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let modified_seconds = get_modified_seconds(modified);
+    let etag = format!("\"{:x}-{:x}\"", modified_seconds, file_size);
+    let last_modified = httpdate::fmt_http_date(modified);
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(ETAG, get_header_value(&etag)?);
+    response_headers.insert(LAST_MODIFIED, get_header_value(&last_modified)?);
+    response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if is_not_modified(headers, &etag, modified_seconds) {
+        let mut response = build_response(StatusCode::NOT_MODIFIED, Payload::None);
+        response.headers_mut().extend(response_headers);
+        return Ok(response);
+    }
+    let content_type = mime_guess::from_path(file_path).first_or_octet_stream();
+    response_headers.insert(CONTENT_TYPE, get_header_value(content_type.as_ref())?);
+    let (status, start, length) = match get_range(headers, &etag, &last_modified, file_size) {
+        Ok(Some((start, end))) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, file_size);
+            response_headers.insert(CONTENT_RANGE, get_header_value(&content_range)?);
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        Ok(None) => (StatusCode::OK, 0, file_size),
+        Err(_) => {
+            let content_range = format!("bytes */{}", file_size);
+            let mut response = build_response(StatusCode::RANGE_NOT_SATISFIABLE, Payload::None);
+            response.headers_mut().extend(response_headers);
+            response
+                .headers_mut()
+                .insert(CONTENT_RANGE, get_header_value(&content_range)?);
+            return Ok(response);
+        }
+    };
+    // The encoder drops the content length of the empty payload, so the head response has none.
+    // The small file is sent with the content length, the large one is streamed in chunks.
+    let payload = if method == Method::HEAD {
+        Payload::None
+    } else if length <= STATIC_FILE_CHUNK_SIZE as u64 {
+        let (read_result, buf) = file
+            .read_exact_at(Vec::with_capacity(length as usize), start)
+            .await;
+        read_result.map_err(|e| AppError(e.to_string()))?;
+        Payload::Fixed(FixedPayload::new(Bytes::from(buf)))
+    } else {
+        let (payload, mut sender) = stream_payload_pair();
+        monoio::spawn(async move {
+            if let Err(e) = stream_file(&file, start, length, &mut sender).await {
+                sender.feed_error(std::io::Error::other(e.to_string()).into());
+            }
+        });
+        Payload::Stream(payload)
+    };
+    let mut response = build_response(status, payload);
+    response.headers_mut().extend(response_headers);
+    Ok(response)
+}
+// The monoio file has no metadata, the fstat of the opened file does not block on the path lookup.
+fn get_metadata(file: &File) -> Result<Metadata, AppError> {
+    // The std file only borrows the descriptor, it is never dropped so the descriptor stays open.
+    let std_file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(file.as_raw_fd()) });
+    std_file.metadata().map_err(|e| AppError(e.to_string()))
+}
+// The chunks which are not written yet share the count. A chunk gives its slot back when the
+// response writer drops it after the write, so the file is read only as fast as the client reads.
+#[derive(Default)]
+struct ChunkSlots {
+    in_flight: AtomicUsize,
+    waker: AtomicWaker,
+}
+struct FileChunk {
+    data: Vec<u8>,
+    slots: Arc<ChunkSlots>,
+}
+impl AsRef<[u8]> for FileChunk {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+impl Drop for FileChunk {
+    fn drop(&mut self) {
+        self.slots.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.slots.waker.wake();
+    }
+}
+async fn stream_file(
+    file: &File,
+    start: u64,
+    length: u64,
+    sender: &mut StreamPayloadSender,
+) -> Result<(), AppError> {
+    let slots = Arc::new(ChunkSlots::default());
+    let end = start + length;
+    let mut offset = start;
+    while offset < end {
+        poll_fn(|cx| {
+            slots.waker.register(cx.waker());
+            if slots.in_flight.load(Ordering::Acquire) < STATIC_FILE_MAX_PENDING_CHUNKS {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        let size = (end - offset).min(STATIC_FILE_CHUNK_SIZE as u64);
+        let (read_result, buf) = file
+            .read_exact_at(Vec::with_capacity(size as usize), offset)
+            .await;
+        read_result.map_err(|e| AppError(e.to_string()))?;
+        let in_flight = slots.in_flight.fetch_add(1, Ordering::AcqRel) + 1;
+        sender.feed_data(Some(Bytes::from_owner(FileChunk {
+            data: buf,
+            slots: slots.clone(),
+        })));
+        // The payload drops the chunk at once if the response has been dropped.
+        if slots.in_flight.load(Ordering::Acquire) < in_flight {
+            return Ok(());
+        }
+        offset += size;
+    }
+    sender.feed_data(None);
+    Ok(())
+}
+// The If-None-Match takes precedence over the If-Modified-Since.
+fn is_not_modified(headers: &HeaderMap<HeaderValue>, etag: &str, modified_seconds: u64) -> bool {
+    if let Some(if_none_match) = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|item| item.to_str().ok())
+    {
+        return if_none_match
+            .split(',')
+            .map(|item| item.trim())
+            .any(|item| item == "*" || item.trim_start_matches("W/") == etag);
+    }
+    headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|item| item.to_str().ok())
+        .and_then(|item| httpdate::parse_http_date(item).ok())
+        .and_then(|item| item.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|item| modified_seconds <= item.as_secs())
+}
+// Only a single range is served, the full file is sent for the multiple ranges. Returns the
+// inclusive range or an error if it is not satisfiable.
+fn get_range(
+    headers: &HeaderMap<HeaderValue>,
+    etag: &str,
+    last_modified: &str,
+    file_size: u64,
+) -> Result<Option<(u64, u64)>, ()> {
+    let Some(range) = headers.get(RANGE).and_then(|item| item.to_str().ok()) else {
+        return Ok(None);
+    };
+    if let Some(if_range) = headers.get(IF_RANGE).and_then(|item| item.to_str().ok()) {
+        if if_range != etag && if_range != last_modified {
+            return Ok(None);
+        }
+    }
+    let Some(range) = range.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = range.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(file_size.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, file_size.saturating_sub(1)),
+        (Err(_), Ok(suffix_length)) if start.is_empty() && suffix_length > 0 => (
+            file_size.saturating_sub(suffix_length),
+            file_size.saturating_sub(1),
+        ),
+        _ => return Ok(None),
+    };
+    if start >= file_size {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+fn get_header_value(value: &str) -> Result<HeaderValue, AppError> {
+    HeaderValue::from_str(value).map_err(|e| AppError(e.to_string()))
+}
+fn build_response(status: StatusCode, payload: Payload) -> Response {
+    Response::builder().status(status).body(payload).unwrap()
+}
+fn get_modified_seconds(modified: SystemTime) -> u64 {
+    modified
+        .duration_since(UNIX_EPOCH)
+        .map(|item| item.as_secs())
+        .unwrap_or_default()
+}
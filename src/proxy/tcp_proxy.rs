@@ -0,0 +1,80 @@
+use crate::proxy::proxy_protocol::encode_header;
+use crate::proxy::proxy_protocol::PrefixedStream;
+use crate::vojo::app_error::AppError;
+use crate::vojo::handler::Handler;
+use http::{HeaderMap, Uri};
+use monoio::io::AsyncWriteRent;
+use monoio::io::AsyncWriteRentExt;
+use monoio::io::Splitable;
+use monoio::net::TcpStream;
+use std::net::SocketAddr;
+
+// The first route of the tcp service selects the upstream, the matchers are not used.
+pub async fn handle_tcp_connection(
+    port: i32,
+    handler: Handler,
+    stream: PrefixedStream,
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+) -> Result<(), AppError> {
+    let remote_ip = client_addr.ip().to_string();
+    let (route, base_route_result, upstream_proxy_protocol) = {
+        let app_config = handler
+            .shared_app_config
+            .read()
+            .map_err(|e| AppError(e.to_string()))?;
+        let local_locality = app_config.static_config.locality.clone();
+        let service_config = &app_config
+            .api_service_config
+            .get(&port)
+            .ok_or(AppError(format!("Can not find port {} in config.", port)))?
+            .service_config;
+        let upstream_proxy_protocol = service_config.upstream_proxy_protocol.clone();
+        let route = service_config
+            .routes
+            .first()
+            .ok_or(AppError(format!("The tcp service {} has no route", port)))?;
+        let base_route_result = route.get_base_route(
+            HeaderMap::new(),
+            remote_ip.clone(),
+            Uri::default(),
+            local_locality,
+        );
+        (route.clone(), base_route_result, upstream_proxy_protocol)
+    };
+    if !route.is_allowed(remote_ip, None).await? {
+        return Ok(());
+    }
+    let base_route = base_route_result?;
+    let upstream_addr = base_route
+        .endpoint
+        .split_once("://")
+        .map(|(_, addr)| addr)
+        .unwrap_or(&base_route.endpoint)
+        .trim_end_matches('/');
+    let mut upstream_stream = TcpStream::connect(upstream_addr)
+        .await
+        .map_err(|e| AppError(format!("Connect to {} error,{}", upstream_addr, e)))?;
+    let _in_flight_guard = base_route.load_status.start_request();
+    if let Some(version) = &upstream_proxy_protocol {
+        let header = encode_header(version, client_addr, local_addr);
+        let (write_result, _) = upstream_stream.write_all(header).await;
+        write_result.map_err(|e| AppError(e.to_string()))?;
+    }
+    let (mut client_read, mut client_write) = stream.into_split();
+    let (mut upstream_read, mut upstream_write) = upstream_stream.into_split();
+    let client_to_upstream = async {
+        let result = monoio::io::copy(&mut client_read, &mut upstream_write).await;
+        let _ = upstream_write.shutdown().await;
+        result
+    };
+    let upstream_to_client = async {
+        let result = monoio::io::copy(&mut upstream_read, &mut client_write).await;
+        let _ = client_write.shutdown().await;
+        result
+    };
+    let (client_result, upstream_result) = monoio::join!(client_to_upstream, upstream_to_client);
+    client_result.map_err(|e| AppError(e.to_string()))?;
+    upstream_result.map_err(|e| AppError(e.to_string()))?;
+    Ok(())
+}
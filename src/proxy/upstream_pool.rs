@@ -0,0 +1,618 @@
+use crate::constants::common_constants::DEFAULT_POOL_SWEEP_INTERVAL_SECONDS;
+use crate::ensure;
+use crate::vojo::app_config::Route;
+use crate::vojo::app_error::AppError;
+use crate::vojo::connection_pool::ConnectionPoolConfig;
+use crate::vojo::connection_pool::Http2Config;
+use crate::vojo::connection_pool::PoolStats;
+use crate::vojo::connection_pool::UpstreamProtocol;
+use crate::vojo::size_limits::SizeLimitViolation;
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderName, CONNECTION, HOST, TE, TRANSFER_ENCODING, UPGRADE};
+use http::uri::Authority;
+use http::{HeaderMap, Method, Uri, Version};
+use monoio::io::{sink::SinkExt, stream::Stream, AsyncReadRent, AsyncWriteRent, AsyncWriteRentExt};
+use monoio::net::TcpStream;
+use monoio_http::h2::client::{Builder, SendRequest};
+use monoio_http::{
+    common::{
+        body::{Body, BodyExt, HttpBody},
+        request::Request,
+        response::Response,
+    },
+    h1::{
+        codec::ClientCodec,
+        payload::{FixedPayload, Payload},
+    },
+};
+use monoio_http_client::unified::{UnifiedTransportConnection, UnifiedTransportConnector};
+use monoio_http_client::{Connector, Key};
+use monoio_rustls::ClientTlsStream;
+use rustls::{ClientConfig, ClientConnection, OwnedTrustAnchor, RootCertStore, ServerName};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const HTTP2_ALPN_PROTOCOL: &[u8] = b"h2";
+const TLS_HANDSHAKE_BUFFER_SIZE: usize = 16 * 1024;
+// The connection specific headers are not allowed in http2.
+const HTTP2_CONNECTION_HEADERS: [HeaderName; 4] = [
+    CONNECTION,
+    TRANSFER_ENCODING,
+    UPGRADE,
+    HeaderName::from_static("keep-alive"),
+];
+
+// The id of the route pool and the upstream.
+type PoolKey = (String, Key);
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum UpstreamError {
+    #[error("{0}")]
+    SizeLimit(SizeLimitViolation),
+    #[error(transparent)]
+    App(#[from] AppError),
+}
+
+struct PooledConnection {
+    codec: ClientCodec<UnifiedTransportConnection>,
+    created_at: Instant,
+    idle_at: Instant,
+    request_count: u64,
+    idle_timeout: Duration,
+    max_lifetime: Option<Duration>,
+    stats: PoolStats,
+}
+impl PooledConnection {
+    fn is_expired(&self) -> bool {
+        self.idle_at.elapsed() > self.idle_timeout
+            || self
+                .max_lifetime
+                .is_some_and(|max_lifetime| self.created_at.elapsed() >= max_lifetime)
+    }
+}
+struct Http2Connection {
+    send_request: SendRequest<Bytes>,
+    created_at: Instant,
+    idle_at: Instant,
+    request_count: u64,
+    idle_timeout: Duration,
+    max_lifetime: Option<Duration>,
+    stats: PoolStats,
+}
+impl Http2Connection {
+    fn is_expired(&self) -> bool {
+        self.send_request.has_conn_error()
+            || (self.send_request.num_active_streams() == 0
+                && self.idle_at.elapsed() > self.idle_timeout)
+            || self
+                .max_lifetime
+                .is_some_and(|max_lifetime| self.created_at.elapsed() >= max_lifetime)
+    }
+}
+// The connections to the upstreams of a worker, keyed by the route and the upstream. The most
+// recently used http1 connection is reused first, the http2 connections are shared by the
+// requests until they have too many streams.
+#[derive(Clone)]
+pub struct UpstreamPool {
+    idle_connections: Rc<RefCell<HashMap<PoolKey, VecDeque<PooledConnection>>>>,
+    http2_connections: Rc<RefCell<HashMap<PoolKey, Vec<Http2Connection>>>>,
+    connector: UnifiedTransportConnector,
+    http2_tls_config: Arc<ClientConfig>,
+}
+impl Default for UpstreamPool {
+    fn default() -> Self {
+        let mut root_store = RootCertStore::empty();
+        root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|item| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                item.subject,
+                item.spki,
+                item.name_constraints,
+            )
+        }));
+        let mut tls_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        tls_config.alpn_protocols = vec![HTTP2_ALPN_PROTOCOL.to_vec()];
+        Self {
+            idle_connections: Default::default(),
+            http2_connections: Default::default(),
+            connector: Default::default(),
+            http2_tls_config: Arc::new(tls_config),
+        }
+    }
+}
+impl UpstreamPool {
+    // Closes the expired idle connections in the background until the pool is dropped.
+    pub fn start_sweep_task(&self) {
+        let idle_connections = Rc::downgrade(&self.idle_connections);
+        let http2_connections = Rc::downgrade(&self.http2_connections);
+        monoio::spawn(async move {
+            let interval = Duration::from_secs(DEFAULT_POOL_SWEEP_INTERVAL_SECONDS);
+            loop {
+                monoio::time::sleep(interval).await;
+                let (Some(idle_connections), Some(http2_connections)) =
+                    (idle_connections.upgrade(), http2_connections.upgrade())
+                else {
+                    return;
+                };
+                idle_connections.borrow_mut().retain(|_, queue| {
+                    queue.retain(|connection| {
+                        let is_expired = connection.is_expired();
+                        if is_expired {
+                            connection.stats.on_closed(true);
+                        }
+                        !is_expired
+                    });
+                    !queue.is_empty()
+                });
+                http2_connections.borrow_mut().retain(|_, connections| {
+                    retain_http2_connections(connections, None);
+                    !connections.is_empty()
+                });
+            }
+        });
+    }
+    pub async fn send_request(
+        &self,
+        pool_id: &str,
+        route: &Route,
+        request: Request<HttpBody>,
+    ) -> Result<Response, UpstreamError> {
+        let key = Key::try_from(request.uri()).map_err(|e| AppError(e.to_string()))?;
+        let pool_key = (pool_id.to_string(), key);
+        let pool_config = route.connection_pool.clone().unwrap_or_default();
+        let max_body_size = route
+            .size_limits
+            .as_ref()
+            .and_then(|item| item.max_response_body_size);
+        match route.upstream_protocol {
+            UpstreamProtocol::Http1 => {
+                self.send_http1_request(
+                    pool_key,
+                    &pool_config,
+                    &route.pool_stats,
+                    max_body_size,
+                    request,
+                )
+                .await
+            }
+            UpstreamProtocol::H2c | UpstreamProtocol::H2 => {
+                let http2_config = route.http2_config.clone().unwrap_or_default();
+                let send_request =
+                    match self.get_http2_connection(&pool_key, &pool_config, &http2_config) {
+                        Some(send_request) => send_request,
+                        None => {
+                            self.connect_http2(
+                                pool_key,
+                                &route.upstream_protocol,
+                                &pool_config,
+                                &http2_config,
+                                &route.pool_stats,
+                            )
+                            .await?
+                        }
+                    };
+                send_http2_request(send_request, max_body_size, request).await
+            }
+        }
+    }
+    async fn send_http1_request(
+        &self,
+        pool_key: PoolKey,
+        pool_config: &ConnectionPoolConfig,
+        pool_stats: &PoolStats,
+        max_body_size: Option<usize>,
+        request: Request<HttpBody>,
+    ) -> Result<Response, UpstreamError> {
+        let is_head = request.method() == Method::HEAD;
+        let is_idempotent = is_idempotent(request.method());
+        let mut is_reusable = !is_connection_close(request.headers(), request.version());
+        // The request is copied before the idle connection is taken, so an error leaves the
+        // connection in the pool. The copy is only kept for the reused connection.
+        let (mut request, retry_request) = get_retry_request(request).await?;
+        let (mut connection, mut retry_request) = match self.get_idle_connection(&pool_key) {
+            Some(connection) => (connection, retry_request),
+            None => (
+                self.connect(&pool_key.1, pool_config, pool_stats).await?,
+                None,
+            ),
+        };
+        // The upstream may have closed the idle connection, so the request is sent once more on a
+        // new connection if the request is idempotent or it has not been sent.
+        let response = loop {
+            connection.request_count += 1;
+            let (is_sent, response_result) = match connection.codec.send_and_flush(request).await {
+                Err(e) => (false, Err(AppError(e.to_string()))),
+                Ok(_) => match connection.codec.next().await {
+                    Some(Ok(response)) => (true, Ok(response)),
+                    Some(Err(e)) => (true, Err(AppError(e.to_string()))),
+                    None => (
+                        true,
+                        Err(AppError(String::from(
+                            "The upstream has closed the connection",
+                        ))),
+                    ),
+                },
+            };
+            match response_result {
+                Ok(response) => break response,
+                Err(e) => {
+                    connection.stats.on_closed(false);
+                    match retry_request.take() {
+                        Some(next_request) if is_idempotent || !is_sent => {
+                            debug!("Send the request again on a new connection,{}", e);
+                            request = next_request;
+                            connection = self.connect(&pool_key.1, pool_config, pool_stats).await?;
+                        }
+                        _ => return Err(e.into()),
+                    }
+                }
+            }
+        };
+        let (parts, payload_decoder) = response.into_parts();
+        let mut body = BytesMut::new();
+        // The response of a HEAD request has the headers of the body but not the body.
+        if !is_head {
+            let mut framed_payload = payload_decoder.with_io(&mut connection.codec);
+            while let Some(data_result) = framed_payload.next_data().await {
+                match data_result {
+                    // The rest of the body is not read, so the connection is dropped.
+                    Ok(data) if max_body_size.is_some_and(|max| body.len() + data.len() > max) => {
+                        connection.stats.on_closed(false);
+                        return Err(UpstreamError::SizeLimit(
+                            SizeLimitViolation::ResponseBodyTooLarge,
+                        ));
+                    }
+                    Ok(data) => body.extend_from_slice(&data),
+                    Err(e) => {
+                        connection.stats.on_closed(false);
+                        return Err(AppError(e.to_string()).into());
+                    }
+                }
+            }
+        }
+        is_reusable = is_reusable
+            && !is_connection_close(&parts.headers, parts.version)
+            && pool_config
+                .max_requests_per_connection
+                .is_none_or(|max_requests| connection.request_count < max_requests);
+        self.release_connection(pool_key, connection, pool_config, is_reusable);
+        Ok(Response::from_parts(
+            parts,
+            Payload::Fixed(FixedPayload::new(body.freeze())),
+        ))
+    }
+    fn get_idle_connection(&self, pool_key: &PoolKey) -> Option<PooledConnection> {
+        let mut idle_connections = self.idle_connections.borrow_mut();
+        let queue = idle_connections.get_mut(pool_key)?;
+        while let Some(connection) = queue.pop_back() {
+            if connection.is_expired() {
+                connection.stats.on_closed(true);
+                continue;
+            }
+            connection.stats.on_reused();
+            return Some(connection);
+        }
+        None
+    }
+    async fn connect(
+        &self,
+        key: &Key,
+        pool_config: &ConnectionPoolConfig,
+        pool_stats: &PoolStats,
+    ) -> Result<PooledConnection, AppError> {
+        let io = self
+            .connector
+            .connect(key.clone())
+            .await
+            .map_err(|e| AppError(format!("Connect to {} error,{}", key, e)))?;
+        pool_stats.on_created();
+        let now = Instant::now();
+        Ok(PooledConnection {
+            codec: ClientCodec::new(io),
+            created_at: now,
+            idle_at: now,
+            request_count: 0,
+            idle_timeout: pool_config.get_idle_timeout(),
+            max_lifetime: pool_config.get_max_lifetime(),
+            stats: pool_stats.clone(),
+        })
+    }
+    fn release_connection(
+        &self,
+        pool_key: PoolKey,
+        mut connection: PooledConnection,
+        pool_config: &ConnectionPoolConfig,
+        is_reusable: bool,
+    ) {
+        connection.idle_at = Instant::now();
+        // The settings may have been changed since the connection was created.
+        connection.idle_timeout = pool_config.get_idle_timeout();
+        connection.max_lifetime = pool_config.get_max_lifetime();
+        let mut idle_connections = self.idle_connections.borrow_mut();
+        let queue = idle_connections.entry(pool_key).or_default();
+        if !is_reusable || connection.is_expired() || queue.len() >= pool_config.max_idle_per_host {
+            connection.stats.on_closed(false);
+            return;
+        }
+        connection.stats.on_released();
+        queue.push_back(connection);
+    }
+    fn get_http2_connection(
+        &self,
+        pool_key: &PoolKey,
+        pool_config: &ConnectionPoolConfig,
+        http2_config: &Http2Config,
+    ) -> Option<SendRequest<Bytes>> {
+        let mut http2_connections = self.http2_connections.borrow_mut();
+        let connections = http2_connections.get_mut(pool_key)?;
+        retain_http2_connections(connections, pool_config.max_requests_per_connection);
+        let connection = connections
+            .iter_mut()
+            .filter(|item| {
+                item.send_request.num_active_streams() < http2_config.max_concurrent_streams
+            })
+            .min_by_key(|item| item.send_request.num_active_streams())?;
+        connection.request_count += 1;
+        connection.idle_at = Instant::now();
+        connection.idle_timeout = pool_config.get_idle_timeout();
+        connection.max_lifetime = pool_config.get_max_lifetime();
+        connection.stats.on_multiplexed();
+        Some(connection.send_request.clone())
+    }
+    async fn connect_http2(
+        &self,
+        pool_key: PoolKey,
+        upstream_protocol: &UpstreamProtocol,
+        pool_config: &ConnectionPoolConfig,
+        http2_config: &Http2Config,
+        pool_stats: &PoolStats,
+    ) -> Result<SendRequest<Bytes>, AppError> {
+        let key = &pool_key.1;
+        let builder = http2_config.get_builder();
+        let send_request = match (upstream_protocol, key.server_name.clone()) {
+            (UpstreamProtocol::H2, Some(server_name)) => {
+                let mut stream = TcpStream::connect((key.host.as_str(), key.port))
+                    .await
+                    .map_err(|e| AppError(format!("Connect to {} error,{}", key, e)))?;
+                let _ = stream.set_nodelay(true);
+                let session = connect_tls(self.http2_tls_config.clone(), server_name, &mut stream)
+                    .await
+                    .map_err(|e| AppError(format!("Connect to {} error,{}", key, e)))?;
+                // Only the h2 is offered, the server which does not support it may still accept
+                // the connection without a protocol.
+                ensure!(
+                    session.alpn_protocol() == Some(HTTP2_ALPN_PROTOCOL),
+                    format!(
+                        "The upstream {} did not negotiate the h2, use the http1 upstream protocol",
+                        key
+                    )
+                );
+                let tls_stream = ClientTlsStream::new(stream, session);
+                http2_handshake(&builder, tls_stream).await?
+            }
+            (UpstreamProtocol::H2c, None) => {
+                let io = self
+                    .connector
+                    .connect(key.clone())
+                    .await
+                    .map_err(|e| AppError(format!("Connect to {} error,{}", key, e)))?;
+                http2_handshake(&builder, io).await?
+            }
+            (UpstreamProtocol::H2, None) => {
+                return Err(AppError(format!(
+                    "The upstream protocol h2 requires the https endpoint,{}",
+                    key
+                )))
+            }
+            _ => {
+                return Err(AppError(format!(
+                    "The upstream protocol h2c requires the http endpoint,{}",
+                    key
+                )))
+            }
+        };
+        pool_stats.on_created();
+        let now = Instant::now();
+        self.http2_connections
+            .borrow_mut()
+            .entry(pool_key)
+            .or_default()
+            .push(Http2Connection {
+                send_request: send_request.clone(),
+                created_at: now,
+                idle_at: now,
+                request_count: 1,
+                idle_timeout: pool_config.get_idle_timeout(),
+                max_lifetime: pool_config.get_max_lifetime(),
+                stats: pool_stats.clone(),
+            });
+        Ok(send_request)
+    }
+}
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+// The body of the streamed request can not be read again, so only the request with the buffered
+// body is copied.
+async fn get_retry_request(
+    request: Request<HttpBody>,
+) -> Result<(Request<HttpBody>, Option<Request<HttpBody>>), AppError> {
+    let (parts, mut body) = request.into_parts();
+    let data = match body {
+        HttpBody::Ready(_) | HttpBody::H1(Payload::None) | HttpBody::H1(Payload::Fixed(_)) => body
+            .next_data()
+            .await
+            .transpose()
+            .map_err(|e| AppError(e.to_string()))?,
+        _ => return Ok((Request::from_parts(parts, body), None)),
+    };
+    let get_body = |data: &Option<Bytes>| match data {
+        Some(data) => HttpBody::H1(Payload::Fixed(FixedPayload::new(data.clone()))),
+        None => HttpBody::H1(Payload::None),
+    };
+    let mut retry_request = Request::new(get_body(&data));
+    *retry_request.method_mut() = parts.method.clone();
+    *retry_request.uri_mut() = parts.uri.clone();
+    *retry_request.version_mut() = parts.version;
+    *retry_request.headers_mut() = parts.headers.clone();
+    Ok((
+        Request::from_parts(parts, get_body(&data)),
+        Some(retry_request),
+    ))
+}
+fn is_connection_close(headers: &HeaderMap, version: Version) -> bool {
+    let connection_header = headers
+        .get(CONNECTION)
+        .and_then(|item| item.to_str().ok())
+        .map(|item| item.to_ascii_lowercase());
+    match connection_header {
+        Some(value) if value.contains("close") => true,
+        Some(value) if value.contains("keep-alive") => false,
+        _ => version < Version::HTTP_11,
+    }
+}
+// The connections are closed gracefully, the streams in flight hold their own handles.
+fn retain_http2_connections(
+    connections: &mut Vec<Http2Connection>,
+    max_requests_option: Option<u64>,
+) {
+    connections.retain(|connection| {
+        let is_closed = connection.is_expired()
+            || max_requests_option
+                .is_some_and(|max_requests| connection.request_count >= max_requests);
+        if is_closed {
+            connection.stats.on_closed(false);
+        }
+        !is_closed
+    });
+}
+// The handshake is driven here because the client stream of the monoio-rustls does not expose the
+// negotiated protocol. Every record which is read is passed to the session, so the records which
+// follow the handshake are kept in the session.
+async fn connect_tls(
+    tls_config: Arc<ClientConfig>,
+    server_name: ServerName,
+    stream: &mut TcpStream,
+) -> Result<ClientConnection, AppError> {
+    let mut session =
+        ClientConnection::new(tls_config, server_name).map_err(|e| AppError(e.to_string()))?;
+    loop {
+        while session.wants_write() {
+            let mut data = Vec::new();
+            session
+                .write_tls(&mut data)
+                .map_err(|e| AppError(e.to_string()))?;
+            let (write_result, _) = stream.write_all(data).await;
+            write_result.map_err(|e| AppError(e.to_string()))?;
+        }
+        if !session.is_handshaking() {
+            return Ok(session);
+        }
+        let (read_result, data) = stream
+            .read(Vec::with_capacity(TLS_HANDSHAKE_BUFFER_SIZE))
+            .await;
+        let size = read_result.map_err(|e| AppError(e.to_string()))?;
+        ensure!(size > 0, "The connection is closed in the tls handshake");
+        let mut data = &data[..size];
+        while !data.is_empty() {
+            session
+                .read_tls(&mut data)
+                .map_err(|e| AppError(e.to_string()))?;
+            session
+                .process_new_packets()
+                .map_err(|e| AppError(e.to_string()))?;
+        }
+    }
+}
+async fn http2_handshake<IO>(builder: &Builder, io: IO) -> Result<SendRequest<Bytes>, AppError>
+where
+    IO: AsyncReadRent + AsyncWriteRent + Unpin + 'static,
+{
+    let (send_request, connection) = builder
+        .handshake::<_, Bytes>(io)
+        .await
+        .map_err(|e| AppError(format!("The http2 handshake error,{}", e)))?;
+    monoio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("The http2 connection to the upstream is closed,{}", e);
+        }
+    });
+    Ok(send_request)
+}
+async fn send_http2_request(
+    send_request: SendRequest<Bytes>,
+    max_body_size: Option<usize>,
+    request: Request<HttpBody>,
+) -> Result<Response, UpstreamError> {
+    let (mut parts, body) = request.into_parts();
+    let body = body.bytes().await.map_err(|e| AppError(e.to_string()))?;
+    // The host of the downstream request is kept as the authority.
+    if let Some(authority) = parts
+        .headers
+        .get(HOST)
+        .and_then(|item| item.to_str().ok())
+        .and_then(|item| item.parse::<Authority>().ok())
+    {
+        let mut uri_parts = parts.uri.into_parts();
+        uri_parts.authority = Some(authority);
+        parts.uri = Uri::from_parts(uri_parts).map_err(|e| AppError(e.to_string()))?;
+    }
+    for header_name in HTTP2_CONNECTION_HEADERS {
+        parts.headers.remove(header_name);
+    }
+    if parts
+        .headers
+        .get(TE)
+        .is_some_and(|item| item.as_bytes() != b"trailers")
+    {
+        parts.headers.remove(TE);
+    }
+    parts.version = Version::HTTP_2;
+    let mut send_request = send_request
+        .ready()
+        .await
+        .map_err(|e| AppError(e.to_string()))?;
+    let (response_future, mut send_stream) = send_request
+        .send_request(http::Request::from_parts(parts, ()), body.is_empty())
+        .map_err(|e| AppError(e.to_string()))?;
+    if !body.is_empty() {
+        send_stream
+            .send_data(body, true)
+            .map_err(|e| AppError(e.to_string()))?;
+    }
+    let response = response_future.await.map_err(|e| AppError(e.to_string()))?;
+    let (mut parts, mut recv_stream) = response.into_parts();
+    let mut body = BytesMut::new();
+    while let Some(data_result) = recv_stream.data().await {
+        let data = data_result.map_err(|e| AppError(e.to_string()))?;
+        if max_body_size.is_some_and(|max| body.len() + data.len() > max) {
+            return Err(UpstreamError::SizeLimit(
+                SizeLimitViolation::ResponseBodyTooLarge,
+            ));
+        }
+        let _ = recv_stream.flow_control().release_capacity(data.len());
+        body.extend_from_slice(&data);
+    }
+    // The downstream is http1, so the trailers are sent as the headers.
+    if let Some(trailers) = recv_stream
+        .trailers()
+        .await
+        .map_err(|e| AppError(e.to_string()))?
+    {
+        parts.headers.extend(trailers);
+    }
+    parts.version = Version::HTTP_11;
+    Ok(Response::from_parts(
+        parts,
+        Payload::Fixed(FixedPayload::new(body.freeze())),
+    ))
+}
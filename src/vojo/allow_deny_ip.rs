@@ -1,6 +1,8 @@
+use ipnet::IpNet;
 use ipnet::Ipv4Net;
 use iprange::IpRange;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
 
 use super::app_error::AppError;
@@ -60,3 +62,20 @@ impl AllowDenyObject {
         Ok(AllowResult::Notmapping)
     }
 }
+// The invalid cidrs never match, so they are rejected when the config is loaded.
+pub fn check_cidr(cidr: &str) -> Result<(), AppError> {
+    if cidr.parse::<IpNet>().is_err() && cidr.parse::<IpAddr>().is_err() {
+        return Err(AppError(format!("Invalid cidr {}", cidr)));
+    }
+    Ok(())
+}
+// The cidrs may also be single ips, e.g. "10.0.0.0/8" or "10.0.0.1".
+pub fn ip_is_in_cidrs(ip: &IpAddr, cidrs: &[String]) -> bool {
+    cidrs.iter().any(|item| {
+        if let Ok(ip_net) = item.parse::<IpNet>() {
+            ip_net.contains(ip)
+        } else {
+            item.parse::<IpAddr>().is_ok_and(|cidr_ip| cidr_ip == *ip)
+        }
+    })
+}
@@ -1,26 +1,424 @@
 use super::allow_deny_ip::AllowResult;
 
+use crate::constants::common_constants::DEFAULT_AFFINITY_COOKIE_NAME;
+use crate::constants::common_constants::DEFAULT_AFFINITY_COOKIE_PATH;
+use crate::constants::common_constants::DEFAULT_CLUSTER_NAME;
+use crate::constants::common_constants::DEFAULT_SLOW_START_MIN_WEIGHT_PERCENT;
+use crate::constants::common_constants::MAX_REGEX_CACHE_SIZE;
+use crate::vojo::allow_deny_ip::check_cidr;
 use crate::vojo::allow_deny_ip::AllowDenyObject;
 
+use crate::ensure;
 use crate::vojo::app_error::AppError;
 use crate::vojo::authentication::AuthenticationStrategy;
+use crate::vojo::cache_config::CacheConfig;
+use crate::vojo::compression::CompressionConfig;
+use crate::vojo::connection_pool::ConnectionPoolConfig;
+use crate::vojo::connection_pool::Http2Config;
+use crate::vojo::connection_pool::PoolStats;
+use crate::vojo::connection_pool::UpstreamProtocol;
+use crate::vojo::cors::CorsConfig;
+use crate::vojo::discovery_provider::DiscoveryProvider;
+use crate::vojo::forwarding_headers::ForwardingHeadersConfig;
+use crate::vojo::header_operations::check_header;
+use crate::vojo::header_operations::get_header_name;
+use crate::vojo::header_operations::HeaderOperations;
+use crate::vojo::header_operations::HeaderTemplateContext;
 use crate::vojo::rate_limit::RatelimitStrategy;
+use crate::vojo::route::get_cookie_value;
+use crate::vojo::route::BaseRoute;
+use crate::vojo::route::LoadStatus;
 use crate::vojo::route::LoadbalancerStrategy;
+use crate::vojo::route::Locality;
+use crate::vojo::route_action::RouteAction;
+use crate::vojo::route_table::RouteTable;
+use crate::vojo::size_limits::SizeLimits;
+use http::header::HOST;
 use http::HeaderMap;
 use http::HeaderValue;
+use http::Method;
+use http::Uri;
+use rand::thread_rng;
+use rand::Rng;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::sync::mpsc;
+thread_local! {
+    static REGEX_CACHE: RefCell<HashMap<String, Rc<Regex>>> = RefCell::new(HashMap::new());
+}
+// The regexes of the config are compiled once per worker, the regex is not cloned because the
+// clone has to allocate its own search cache. The patterns of the removed routes are never looked
+// up again, so the cache is cleared when it is full and the live ones are compiled again.
+pub fn get_cached_regex(pattern: &str) -> Result<Rc<Regex>, AppError> {
+    REGEX_CACHE.with(|regex_cache| {
+        if let Some(regex) = regex_cache.borrow().get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Rc::new(Regex::new(pattern).map_err(|e| AppError(e.to_string()))?);
+        let mut regex_cache = regex_cache.borrow_mut();
+        if regex_cache.len() >= MAX_REGEX_CACHE_SIZE {
+            regex_cache.clear();
+        }
+        regex_cache.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    })
+}
+// The path is matched by the exact path, then the regex path, then the prefix. A request
+// matches only when all of the conditions hold.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Matcher {
+    #[serde(default)]
     pub prefix: String,
+    #[serde(default)]
     pub prefix_rewrite: String,
+    pub path: Option<String>,
+    pub regex_path: Option<String>,
+    // The new path of the exact path, or the template of the regex path, e.g. "/users/${id}".
+    pub path_rewrite: Option<String>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<HeaderMatcher>>,
+    pub query_params: Option<Vec<QueryParamMatcher>>,
+    // Applied to the path after the rewrites above.
+    pub regex_rewrite: Option<RegexRewrite>,
+}
+// All the matches of the pattern are replaced, the substitution may use the capture groups.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct RegexRewrite {
+    pub pattern: String,
+    pub substitution: String,
+}
+impl Matcher {
+    // Returns the path which is sent to the upstream if the request is matched.
+    pub fn get_matched_path(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Option<String>, AppError> {
+        let path = uri.path();
+        let final_path = if let Some(exact_path) = &self.path {
+            if path != exact_path {
+                return Ok(None);
+            }
+            self.path_rewrite
+                .clone()
+                .unwrap_or_else(|| path.to_string())
+        } else if let Some(regex_path) = &self.regex_path {
+            let regex = get_cached_regex(regex_path)?;
+            let Some(captures) = regex.captures(path) else {
+                return Ok(None);
+            };
+            match &self.path_rewrite {
+                Some(path_rewrite) => {
+                    let mut final_path = String::new();
+                    captures.expand(path_rewrite, &mut final_path);
+                    final_path
+                }
+                None => path.to_string(),
+            }
+        } else {
+            let Some(rest_path) = path.strip_prefix(self.prefix.as_str()) else {
+                return Ok(None);
+            };
+            format!("{}{}", self.prefix_rewrite, rest_path)
+        };
+        let final_path = match &self.regex_rewrite {
+            Some(regex_rewrite) => get_cached_regex(&regex_rewrite.pattern)?
+                .replace_all(&final_path, regex_rewrite.substitution.as_str())
+                .into_owned(),
+            None => final_path,
+        };
+        if let Some(methods) = &self.methods {
+            if !methods
+                .iter()
+                .any(|item| item.eq_ignore_ascii_case(method.as_str()))
+            {
+                return Ok(None);
+            }
+        }
+        for header_matcher in self.headers.iter().flatten() {
+            if !header_matcher.is_matched(headers)? {
+                return Ok(None);
+            }
+        }
+        for query_param_matcher in self.query_params.iter().flatten() {
+            if !query_param_matcher.is_matched(uri)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(final_path))
+    }
+}
+// The header is only required to be present if neither the value nor the regex is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct HeaderMatcher {
+    pub name: String,
+    pub value: Option<String>,
+    pub regex: Option<String>,
+}
+impl HeaderMatcher {
+    fn is_matched(&self, headers: &HeaderMap<HeaderValue>) -> Result<bool, AppError> {
+        let values = headers
+            .get_all(self.name.as_str())
+            .iter()
+            .filter_map(|item| item.to_str().ok());
+        is_value_matched(values, self.value.as_deref(), self.regex.as_deref())
+    }
+}
+// The param is only required to be present if neither the value nor the regex is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct QueryParamMatcher {
+    pub name: String,
+    pub value: Option<String>,
+    pub regex: Option<String>,
+}
+impl QueryParamMatcher {
+    fn is_matched(&self, uri: &Uri) -> Result<bool, AppError> {
+        let values = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .map(|item| item.split_once('=').unwrap_or((item, "")))
+            .filter(|(key, _)| *key == self.name)
+            .map(|(_, value)| value);
+        is_value_matched(values, self.value.as_deref(), self.regex.as_deref())
+    }
+}
+fn is_value_matched<'a>(
+    mut values: impl Iterator<Item = &'a str>,
+    value_option: Option<&str>,
+    regex_option: Option<&str>,
+) -> Result<bool, AppError> {
+    let regex_option = regex_option.map(get_cached_regex).transpose()?;
+    Ok(values.any(|item| {
+        value_option.is_none_or(|value| item == value)
+            && regex_option
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(item))
+    }))
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct LivenessConfig {
     pub min_liveness_count: i32,
 }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionAffinityConfig {
+    #[serde(default = "default_affinity_cookie_name")]
+    pub cookie_name: String,
+    pub ttl_seconds: Option<u64>,
+    #[serde(default = "default_affinity_cookie_path")]
+    pub path: String,
+}
+fn default_affinity_cookie_name() -> String {
+    DEFAULT_AFFINITY_COOKIE_NAME.to_string()
+}
+fn default_affinity_cookie_path() -> String {
+    DEFAULT_AFFINITY_COOKIE_PATH.to_string()
+}
+impl SessionAffinityConfig {
+    pub fn get_set_cookie(&self, base_route_id: &str) -> String {
+        let mut set_cookie = format!(
+            "{}={}; Path={}; HttpOnly",
+            self.cookie_name, base_route_id, self.path
+        );
+        if let Some(ttl_seconds) = self.ttl_seconds {
+            set_cookie.push_str(&format!("; Max-Age={}", ttl_seconds));
+        }
+        set_cookie
+    }
+}
+// The weight of a warming up route is scaled by its factor, so it only applies to the strategies
+// with a weight or a load, the PollRoute, the HashRoute and the HeaderRoute are rejected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowStartConfig {
+    pub window_seconds: u64,
+    #[serde(default = "default_slow_start_min_weight_percent")]
+    pub min_weight_percent: u64,
+}
+fn default_slow_start_min_weight_percent() -> u64 {
+    DEFAULT_SLOW_START_MIN_WEIGHT_PERCENT
+}
+impl SlowStartConfig {
+    // The part of its share a route is given, rising linearly during the window.
+    pub fn get_weight_factor(&self, load_status: &LoadStatus) -> f64 {
+        let Some(elapsed) = load_status.warm_up_elapsed() else {
+            return 1.0;
+        };
+        let window = Duration::from_secs(self.window_seconds);
+        if window.is_zero() || elapsed >= window {
+            return 1.0;
+        }
+        let min_factor = self.min_weight_percent.min(100) as f64 / 100.0;
+        (elapsed.as_secs_f64() / window.as_secs_f64()).max(min_factor)
+    }
+}
+// Sends a copy of a part of the requests to another cluster, the responses are thrown away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    // From 0 to 100.
+    pub percentage: f64,
+    pub route_cluster: LoadbalancerStrategy,
+    // The upstream settings of the mirror cluster, the settings of the route are not used.
+    pub connection_pool: Option<ConnectionPoolConfig>,
+    #[serde(default)]
+    pub upstream_protocol: UpstreamProtocol,
+    pub http2_config: Option<Http2Config>,
+    // Only the response body limit is used, the request is already checked by the route.
+    pub size_limits: Option<SizeLimits>,
+    #[serde(skip)]
+    pub pool_stats: PoolStats,
+}
+impl MirrorConfig {
+    // The upstream pool reads the settings of the upstream from the route.
+    pub fn get_upstream_route(&self, route: &Route) -> Route {
+        let mut mirror_route = route.clone();
+        mirror_route.connection_pool = self.connection_pool.clone();
+        mirror_route.upstream_protocol = self.upstream_protocol.clone();
+        mirror_route.http2_config = self.http2_config.clone();
+        mirror_route.size_limits = self.size_limits.clone();
+        mirror_route.pool_stats = self.pool_stats.clone();
+        mirror_route
+    }
+    fn should_mirror(&self) -> bool {
+        self.percentage >= 100.0
+            || (self.percentage > 0.0 && thread_rng().gen_range(0.0..100.0) < self.percentage)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedCluster {
+    pub name: String,
+    pub weight: u32,
+    pub route_cluster: LoadbalancerStrategy,
+}
+// Forces the matched requests onto the cluster, the route cluster is named "default".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClusterOverride {
+    Header {
+        key: String,
+        value: String,
+        cluster: String,
+    },
+    Cookie {
+        key: String,
+        value: String,
+        cluster: String,
+    },
+}
+impl ClusterOverride {
+    fn get_cluster(&self, headers: &HeaderMap<HeaderValue>) -> Option<&str> {
+        let (is_matched, cluster) = match self {
+            ClusterOverride::Header {
+                key,
+                value,
+                cluster,
+            } => (
+                headers
+                    .get(key.as_str())
+                    .is_some_and(|item| item.as_bytes() == value.as_bytes()),
+                cluster,
+            ),
+            ClusterOverride::Cookie {
+                key,
+                value,
+                cluster,
+            } => (
+                get_cookie_value(headers, key).as_deref() == Some(value.as_str()),
+                cluster,
+            ),
+        };
+        is_matched.then_some(cluster.as_str())
+    }
+}
+// Splits the requests of a route across the route cluster and the weighted clusters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficSplit {
+    pub default_weight: u32,
+    pub clusters: Vec<WeightedCluster>,
+    #[serde(default)]
+    pub overrides: Vec<ClusterOverride>,
+}
+impl TrafficSplit {
+    // Returns the index of the weighted cluster, or none for the route cluster.
+    fn select_cluster(&self, headers: &HeaderMap<HeaderValue>) -> Option<usize> {
+        if let Some(name) = self
+            .overrides
+            .iter()
+            .find_map(|item| item.get_cluster(headers))
+        {
+            return self.clusters.iter().position(|item| item.name == name);
+        }
+        let total_weight: u64 = self.default_weight as u64
+            + self
+                .clusters
+                .iter()
+                .map(|item| item.weight as u64)
+                .sum::<u64>();
+        if total_weight == 0 {
+            return None;
+        }
+        let mut offset = thread_rng().gen_range(0..total_weight);
+        if offset < self.default_weight as u64 {
+            return None;
+        }
+        offset -= self.default_weight as u64;
+        for (index, cluster) in self.clusters.iter().enumerate() {
+            if offset < cluster.weight as u64 {
+                return Some(index);
+            }
+            offset -= cluster.weight as u64;
+        }
+        None
+    }
+    pub fn update_weights(&mut self, weights: HashMap<String, u32>) -> Result<(), AppError> {
+        for name in weights.keys() {
+            if name != DEFAULT_CLUSTER_NAME && !self.clusters.iter().any(|item| item.name == *name)
+            {
+                return Err(AppError(format!("The cluster {} is not found", name)));
+            }
+        }
+        for (name, weight) in weights {
+            if name == DEFAULT_CLUSTER_NAME {
+                self.default_weight = weight;
+            } else if let Some(cluster) = self.clusters.iter_mut().find(|item| item.name == name) {
+                cluster.weight = weight;
+            }
+        }
+        Ok(())
+    }
+    // Keeps the states of the clusters which have the same names.
+    pub fn update_traffic_split(
+        &mut self,
+        new_traffic_split: TrafficSplit,
+    ) -> Result<(), AppError> {
+        let mut clusters = vec![];
+        for mut new_cluster in new_traffic_split.clusters {
+            if let Some(index) = self
+                .clusters
+                .iter()
+                .position(|item| item.name == new_cluster.name)
+            {
+                let mut cluster = self.clusters.remove(index);
+                cluster.weight = new_cluster.weight;
+                cluster
+                    .route_cluster
+                    .update_cluster(new_cluster.route_cluster)?;
+                new_cluster = cluster;
+            }
+            clusters.push(new_cluster);
+        }
+        self.clusters = clusters;
+        self.default_weight = new_traffic_split.default_weight;
+        self.overrides = new_traffic_split.overrides;
+        Ok(())
+    }
+}
 #[derive(Debug, Serialize, Clone, Deserialize, Default)]
 pub struct LivenessStatus {
     pub current_liveness_count: i32,
@@ -28,56 +426,209 @@ pub struct LivenessStatus {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Route {
     pub route_id: String,
+    // The routes with the higher priority are checked first.
+    #[serde(default)]
+    pub priority: i32,
     pub host_name: Option<String>,
     pub matcher: Option<Matcher>,
     pub allow_deny_list: Option<Vec<AllowDenyObject>>,
     pub authentication: Option<Box<dyn AuthenticationStrategy>>,
 
+    // The headers set on the upstream request, before the request header operations.
     pub rewrite_headers: Option<HashMap<String, String>>,
+    pub request_headers: Option<HeaderOperations>,
+    pub response_headers: Option<HeaderOperations>,
+    pub forwarding_headers: Option<ForwardingHeadersConfig>,
+    // The host header sent to the upstream, auto_host_rewrite uses the host of the endpoint.
+    pub host_rewrite: Option<String>,
+    #[serde(default)]
+    pub auto_host_rewrite: bool,
+    // Replaces the compression of the listener.
+    pub compression: Option<CompressionConfig>,
+    // The GET and HEAD responses are cached in each worker.
+    pub cache: Option<CacheConfig>,
+    // The preflight requests are answered by the gateway.
+    pub cors: Option<CorsConfig>,
+    // Replaces the limits of the listener which are set.
+    pub size_limits: Option<SizeLimits>,
     pub liveness_config: Option<LivenessConfig>,
     pub ratelimit: Option<Box<dyn RatelimitStrategy>>,
+    pub session_affinity: Option<SessionAffinityConfig>,
+    pub slow_start_config: Option<SlowStartConfig>,
+    // The name of the discovery provider which produces the endpoints of the route cluster.
+    pub discovery_provider: Option<String>,
+    pub connection_pool: Option<ConnectionPoolConfig>,
+    #[serde(default)]
+    pub upstream_protocol: UpstreamProtocol,
+    pub http2_config: Option<Http2Config>,
+    #[serde(skip)]
+    pub pool_stats: PoolStats,
+    pub mirror: Option<MirrorConfig>,
+    pub traffic_split: Option<TrafficSplit>,
+    // The redirect or the direct response which is sent instead of the route cluster.
+    pub action: Option<RouteAction>,
+    #[serde(default)]
     pub route_cluster: LoadbalancerStrategy,
 }
 
 impl Route {
+    pub fn get_base_route(
+        &self,
+        headers: HeaderMap<HeaderValue>,
+        remote_ip: String,
+        uri: Uri,
+        local_locality: Option<Locality>,
+    ) -> Result<BaseRoute, AppError> {
+        if let Some(session_affinity) = &self.session_affinity {
+            if let Some(base_route_id) = get_cookie_value(&headers, &session_affinity.cookie_name) {
+                if let Some(base_route) =
+                    self.route_cluster.get_alive_route_by_id(&base_route_id)?
+                {
+                    return Ok(base_route);
+                }
+                for cluster in self
+                    .traffic_split
+                    .iter()
+                    .flat_map(|item| item.clusters.iter())
+                {
+                    if let Some(base_route) = cluster
+                        .route_cluster
+                        .get_alive_route_by_id(&base_route_id)?
+                    {
+                        return Ok(base_route);
+                    }
+                }
+            }
+        }
+        let min_liveness_count = self
+            .liveness_config
+            .as_ref()
+            .map_or(0, |item| item.min_liveness_count);
+        let route_cluster = match &self.traffic_split {
+            Some(traffic_split) => match traffic_split.select_cluster(&headers) {
+                Some(index) => &traffic_split.clusters[index].route_cluster,
+                None => &self.route_cluster,
+            },
+            None => &self.route_cluster,
+        };
+        route_cluster.get_route(
+            headers,
+            remote_ip,
+            uri,
+            local_locality,
+            min_liveness_count,
+            self.slow_start_config.as_ref(),
+        )
+    }
+    fn get_route_clusters(&self) -> impl Iterator<Item = &LoadbalancerStrategy> {
+        std::iter::once(&self.route_cluster).chain(
+            self.traffic_split
+                .iter()
+                .flat_map(|item| item.clusters.iter())
+                .map(|item| &item.route_cluster),
+        )
+    }
+    pub fn check_slow_start(&self) -> Result<(), AppError> {
+        if self.slow_start_config.is_none() {
+            return Ok(());
+        }
+        ensure!(
+            self.get_route_clusters()
+                .all(|item| item.supports_slow_start()),
+            "The slow start can not be used with the PollRoute, the HashRoute or the HeaderRoute"
+        );
+        Ok(())
+    }
+    // The routes of a new route start to warm up, as the new routes of an updated cluster do.
+    pub fn get_base_routes(&self) -> impl Iterator<Item = &BaseRoute> {
+        self.get_route_clusters()
+            .flat_map(|item| item.get_base_routes())
+    }
+    pub fn start_warm_up(&self) {
+        for base_route in self.get_base_routes() {
+            base_route.load_status.start_warm_up();
+        }
+    }
+    // Returns the route of the mirror cluster if the request is picked for mirroring.
+    pub fn get_mirror_base_route(
+        &self,
+        headers: HeaderMap<HeaderValue>,
+        remote_ip: String,
+        uri: Uri,
+        local_locality: Option<Locality>,
+    ) -> Option<BaseRoute> {
+        let mirror = self.mirror.as_ref()?;
+        if !mirror.should_mirror() {
+            return None;
+        }
+        mirror
+            .route_cluster
+            .get_route(headers, remote_ip, uri, local_locality, 0, None)
+            .map_err(|e| debug!("Select the mirror route error,{}", e))
+            .ok()
+    }
     pub fn is_matched(
         &self,
-        path: String,
-        headers_option: Option<HeaderMap<HeaderValue>>,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap<HeaderValue>,
     ) -> Result<Option<String>, AppError> {
-        let matcher = self
-            .clone()
-            .matcher
-            .ok_or("The matcher counld not be none for http")
-            .map_err(|err| AppError(err.to_string()))?;
-
-        let match_res = path.strip_prefix(matcher.prefix.as_str());
-        if match_res.is_none() {
+        let matcher = self.matcher.as_ref().ok_or(AppError(String::from(
+            "The matcher counld not be none for http",
+        )))?;
+        let Some(final_path) = matcher.get_matched_path(method, uri, headers)? else {
             return Ok(None);
-        }
-        let final_path = format!("{}{}", matcher.prefix_rewrite, match_res.unwrap());
-        // info!("final_path:{}", final_path);
+        };
         if let Some(real_host_name) = &self.host_name {
-            if headers_option.is_none() {
+            let Some(host) = headers.get(HOST).and_then(|item| item.to_str().ok()) else {
                 return Ok(None);
-            }
-            let header_map = headers_option.unwrap();
-            let host_option = header_map.get("Host");
-            if host_option.is_none() {
-                return Ok(None);
-            }
-            let host_result = host_option.unwrap().to_str();
-            if host_result.is_err() {
+            };
+            if !get_cached_regex(real_host_name)?.is_match(host) {
                 return Ok(None);
             }
-            let host_name_regex =
-                Regex::new(real_host_name.as_str()).map_err(|e| AppError(e.to_string()))?;
-            return host_name_regex
-                .captures(host_result.unwrap())
-                .map_or(Ok(None), |_| Ok(Some(final_path)));
         }
         Ok(Some(final_path))
     }
+    pub fn has_header_operations(&self) -> bool {
+        self.rewrite_headers.is_some()
+            || self.request_headers.is_some()
+            || self.response_headers.is_some()
+    }
+    pub fn check_header_operations(&self) -> Result<(), AppError> {
+        for (key, value) in self.rewrite_headers.iter().flatten() {
+            check_header(key, value)?;
+        }
+        for header_operations in [&self.request_headers, &self.response_headers]
+            .into_iter()
+            .flatten()
+        {
+            header_operations.check()?;
+        }
+        Ok(())
+    }
+    pub fn apply_request_headers(
+        &self,
+        headers: &mut HeaderMap<HeaderValue>,
+        context: &HeaderTemplateContext,
+    ) -> Result<(), AppError> {
+        for (key, value) in self.rewrite_headers.iter().flatten() {
+            headers.insert(get_header_name(key)?, context.get_header_value(value)?);
+        }
+        if let Some(request_headers) = &self.request_headers {
+            request_headers.apply(headers, context)?;
+        }
+        Ok(())
+    }
+    pub fn apply_response_headers(
+        &self,
+        headers: &mut HeaderMap<HeaderValue>,
+        context: &HeaderTemplateContext,
+    ) -> Result<(), AppError> {
+        if let Some(response_headers) = &self.response_headers {
+            response_headers.apply(headers, context)?;
+        }
+        Ok(())
+    }
     pub async fn is_allowed(
         &self,
         ip: String,
@@ -142,12 +693,71 @@ pub enum ServiceType {
     Http2,
     Http2Tls,
 }
+impl ServiceType {
+    pub fn get_scheme(&self) -> &'static str {
+        match self {
+            ServiceType::Https | ServiceType::Http2Tls => "https",
+            _ => "http",
+        }
+    }
+}
+// The PROXY protocol header is required from the trusted sources and never read from the others.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ProxyProtocolConfig {
+    pub trusted_sources: Vec<String>,
+}
+impl ProxyProtocolConfig {
+    pub fn check(&self) -> Result<(), AppError> {
+        for trusted_source in self.trusted_sources.iter() {
+            check_cidr(trusted_source)?;
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ServiceConfig {
     pub server_type: ServiceType,
     pub cert_str: Option<String>,
     pub key_str: Option<String>,
     pub routes: Vec<Route>,
+    // The path is normalized before the routes are matched.
+    #[serde(default)]
+    pub normalize_path: bool,
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+    // The header sent to the upstreams of the tcp service.
+    pub upstream_proxy_protocol: Option<ProxyProtocolVersion>,
+    pub compression: Option<CompressionConfig>,
+    pub size_limits: Option<SizeLimits>,
+    // Built on the first lookup after the routes are changed, so the lookup only needs the read
+    // lock of the config.
+    #[serde(skip)]
+    route_table: OnceLock<RouteTable>,
+}
+impl ServiceConfig {
+    // Must be called after the matching fields of the routes are changed.
+    pub fn on_routes_changed(&mut self) {
+        self.route_table = OnceLock::new();
+    }
+    fn get_route_table(&self) -> Result<&RouteTable, AppError> {
+        if let Some(route_table) = self.route_table.get() {
+            return Ok(route_table);
+        }
+        let route_table = RouteTable::new(&self.routes)?;
+        Ok(self.route_table.get_or_init(|| route_table))
+    }
+    pub fn get_route_candidates(&self, path: &str) -> Result<Vec<usize>, AppError> {
+        Ok(self.get_route_table()?.get_candidates(path))
+    }
+    pub fn get_static_root(&self, endpoint: &str) -> Result<PathBuf, AppError> {
+        self.get_route_table()?
+            .get_static_root(endpoint)
+            .ok_or(AppError(format!("The root of {} is not found", endpoint)))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -174,9 +784,12 @@ pub struct StaticConfig {
     pub database_url: Option<String>,
     pub admin_port: String,
     pub config_file_path: Option<String>,
+    pub locality: Option<Locality>,
 }
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AppConfig {
     pub static_config: StaticConfig,
     pub api_service_config: HashMap<i32, ApiService>,
+    #[serde(default)]
+    pub discovery_providers: HashMap<String, Box<dyn DiscoveryProvider>>,
 }
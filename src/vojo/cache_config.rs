@@ -0,0 +1,87 @@
+use crate::constants::common_constants::DEFAULT_CACHE_MAX_ENTRY_SIZE;
+use crate::constants::common_constants::DEFAULT_CACHE_MAX_SIZE;
+use crate::constants::common_constants::MAX_CACHE_PURGE_LOG_SIZE;
+use crate::vojo::app_error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+fn default_max_size() -> usize {
+    DEFAULT_CACHE_MAX_SIZE
+}
+fn default_max_entry_size() -> usize {
+    DEFAULT_CACHE_MAX_ENTRY_SIZE
+}
+// Every worker has its own cache, the sizes are the limits of one worker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default = "default_max_size")]
+    pub max_size: usize,
+    #[serde(default = "default_max_entry_size")]
+    pub max_entry_size: usize,
+    // The freshness of the responses without the Cache-Control and the Expires, they are not
+    // cached if it is not set.
+    pub default_ttl_seconds: Option<u64>,
+    // Used when the response has no stale-while-revalidate directive.
+    #[serde(default)]
+    pub stale_while_revalidate_seconds: u64,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_max_size(),
+            max_entry_size: default_max_entry_size(),
+            default_ttl_seconds: None,
+            stale_while_revalidate_seconds: 0,
+        }
+    }
+}
+// The path prefix of the entries to remove, all the listeners are purged if the port is not set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachePurge {
+    pub prefix: String,
+    pub port: Option<i32>,
+    #[serde(skip)]
+    pub generation: u64,
+}
+// The purges are applied by each worker on its next cache lookup, the worker which has missed
+// the purges dropped from the log clears its whole cache.
+#[derive(Debug, Clone, Default)]
+pub struct CachePurgeLog {
+    generation: Arc<AtomicU64>,
+    purges: Arc<Mutex<VecDeque<CachePurge>>>,
+}
+impl CachePurgeLog {
+    pub fn push(&self, mut purge: CachePurge) -> Result<u64, AppError> {
+        let mut purges = self.purges.lock().map_err(|e| AppError(e.to_string()))?;
+        let generation = self.generation.load(Ordering::Acquire) + 1;
+        purge.generation = generation;
+        purges.push_back(purge);
+        if purges.len() > MAX_CACHE_PURGE_LOG_SIZE {
+            purges.pop_front();
+        }
+        self.generation.store(generation, Ordering::Release);
+        Ok(generation)
+    }
+    pub fn get_generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+    // Returns None if some of the purges after the generation have been dropped.
+    pub fn get_purges_after(&self, generation: u64) -> Result<Option<Vec<CachePurge>>, AppError> {
+        let purges = self.purges.lock().map_err(|e| AppError(e.to_string()))?;
+        if purges
+            .front()
+            .is_some_and(|item| item.generation > generation + 1)
+        {
+            return Ok(None);
+        }
+        Ok(Some(
+            purges
+                .iter()
+                .filter(|item| item.generation > generation)
+                .cloned()
+                .collect(),
+        ))
+    }
+}
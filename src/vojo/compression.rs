@@ -0,0 +1,375 @@
+use crate::constants::common_constants::BROTLI_QUALITY;
+use crate::constants::common_constants::BROTLI_WINDOW_SIZE;
+use crate::constants::common_constants::COMPRESSION_CHUNK_SIZE;
+use crate::constants::common_constants::DEFAULT_COMPRESSION_MIN_SIZE;
+use crate::constants::common_constants::ZSTD_LEVEL;
+use crate::vojo::app_error::AppError;
+use crate::vojo::header_operations::append_vary;
+use bytes::Bytes;
+use http::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+    ETAG,
+};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use monoio_http::common::body::Body;
+use monoio_http::common::response::Response;
+use monoio_http::h1::payload::{stream_payload_pair, FixedPayload, Payload};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::task::Poll;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    Br,
+    Zstd,
+    Gzip,
+}
+impl ContentEncoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Br => "br",
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+    fn from_header(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "br" => Some(ContentEncoding::Br),
+            "zstd" => Some(ContentEncoding::Zstd),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            _ => None,
+        }
+    }
+}
+fn default_encodings() -> Vec<ContentEncoding> {
+    vec![
+        ContentEncoding::Br,
+        ContentEncoding::Zstd,
+        ContentEncoding::Gzip,
+    ]
+}
+fn default_min_size() -> usize {
+    DEFAULT_COMPRESSION_MIN_SIZE
+}
+fn default_content_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/json",
+        "application/javascript",
+        "application/xml",
+        "application/wasm",
+        "image/svg+xml",
+    ]
+    .iter()
+    .map(|item| item.to_string())
+    .collect()
+}
+// The route config replaces the listener config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    // The order is the preference of the server when the client accepts several with the same q.
+    #[serde(default = "default_encodings")]
+    pub encodings: Vec<ContentEncoding>,
+    #[serde(default = "default_min_size")]
+    pub min_size: usize,
+    // The media types, e.g. "text/*" or "application/json".
+    #[serde(default = "default_content_types")]
+    pub content_types: Vec<String>,
+    // The compressed request bodies are decoded before they are sent to the upstream.
+    #[serde(default)]
+    pub decompress_requests: bool,
+}
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            encodings: default_encodings(),
+            min_size: default_min_size(),
+            content_types: default_content_types(),
+            decompress_requests: false,
+        }
+    }
+}
+impl CompressionConfig {
+    // The encoding with the highest q value, the "*" covers the encodings which are not listed.
+    fn negotiate(&self, accept_encoding: &str) -> Option<ContentEncoding> {
+        let mut wildcard_quality = None;
+        let mut qualities = vec![];
+        for item in accept_encoding.split(',') {
+            let mut fields = item.split(';');
+            let coding = fields
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let quality = fields
+                .filter_map(|field| field.trim().strip_prefix("q="))
+                .find_map(|field| field.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if coding == "*" {
+                wildcard_quality = Some(quality);
+            } else if let Some(encoding) = ContentEncoding::from_header(&coding) {
+                qualities.push((encoding, quality));
+            }
+        }
+        let mut selected: Option<(ContentEncoding, f32)> = None;
+        for encoding in self.encodings.iter() {
+            let quality = qualities
+                .iter()
+                .find(|(item, _)| item == encoding)
+                .map(|(_, quality)| *quality)
+                .or(wildcard_quality)
+                .unwrap_or(0.0);
+            if quality > 0.0
+                && selected.is_none_or(|(_, selected_quality)| quality > selected_quality)
+            {
+                selected = Some((*encoding, quality));
+            }
+        }
+        selected.map(|(encoding, _)| encoding)
+    }
+    fn is_compressible_type(&self, content_type: &str) -> bool {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types
+            .iter()
+            .any(|item| match item.strip_suffix("/*") {
+                Some(type_prefix) => media_type
+                    .split_once('/')
+                    .is_some_and(|(item_type, _)| item_type.eq_ignore_ascii_case(type_prefix)),
+                None => item.eq_ignore_ascii_case(&media_type),
+            })
+    }
+    // The body is compressed while it is sent, the responses which are already encoded, too small
+    // or not in the content types are sent unchanged.
+    pub async fn compress_response(
+        &self,
+        method: &Method,
+        request_headers: &HeaderMap<HeaderValue>,
+        response: Response,
+    ) -> Result<Response, AppError> {
+        let status = response.status();
+        let headers = response.headers();
+        if method == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+            || headers.contains_key(CONTENT_ENCODING)
+            || has_no_transform(headers)
+        {
+            return Ok(response);
+        }
+        let is_compressible_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|item| item.to_str().ok())
+            .is_some_and(|item| self.is_compressible_type(item));
+        if !is_compressible_type {
+            return Ok(response);
+        }
+        let (mut parts, mut payload) = response.into_parts();
+        // The fixed body is already in memory, the stream only has the content length header.
+        let body_size = match payload {
+            Payload::None => Some(0),
+            Payload::Fixed(_) => {
+                let data = match payload.next_data().await {
+                    Some(data_result) => data_result.map_err(|e| AppError(e.to_string()))?,
+                    None => Bytes::new(),
+                };
+                let body_size = data.len();
+                payload = Payload::Fixed(FixedPayload::new(data));
+                Some(body_size)
+            }
+            Payload::Stream(_) => parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|item| item.to_str().ok())
+                .and_then(|item| item.parse::<usize>().ok()),
+        };
+        if body_size.is_some_and(|item| item < self.min_size) {
+            return Ok(Response::from_parts(parts, payload));
+        }
+        append_vary(&mut parts.headers, "accept-encoding");
+        let encoding_option = request_headers
+            .get(ACCEPT_ENCODING)
+            .and_then(|item| item.to_str().ok())
+            .and_then(|item| self.negotiate(item));
+        let Some(encoding) = encoding_option else {
+            return Ok(Response::from_parts(parts, payload));
+        };
+        let encoder = ContentEncoder::new(encoding)?;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.remove(ACCEPT_RANGES);
+        parts.headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        // The compressed body is not byte-identical, so the strong etag becomes weak.
+        if let Some(etag) = parts
+            .headers
+            .get(ETAG)
+            .and_then(|item| item.to_str().ok())
+            .filter(|item| !item.starts_with("W/"))
+        {
+            let weak_etag = HeaderValue::from_str(&format!("W/{}", etag))
+                .map_err(|e| AppError(e.to_string()))?;
+            parts.headers.insert(ETAG, weak_etag);
+        }
+        let (stream_payload, mut sender) = stream_payload_pair();
+        monoio::spawn(async move {
+            let result = encode_payload(payload, encoder, |data| {
+                sender.feed_data(Some(data));
+            })
+            .await;
+            match result {
+                Ok(()) => sender.feed_data(None),
+                Err(e) => {
+                    error!("Compress the response error,{}", e);
+                    sender.feed_error(std::io::Error::other(e.to_string()).into());
+                }
+            }
+        });
+        Ok(Response::from_parts(parts, Payload::Stream(stream_payload)))
+    }
+}
+fn has_no_transform(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .flat_map(|item| item.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case("no-transform"))
+}
+// The input is fed in chunks and the task yields after each output chunk, so the connection
+// sends the first bytes before the whole body is compressed.
+async fn encode_payload(
+    mut payload: Payload,
+    mut encoder: ContentEncoder,
+    mut feed: impl FnMut(Bytes),
+) -> Result<(), AppError> {
+    while let Some(data_result) = payload.next_data().await {
+        let data = data_result.map_err(|e| AppError(e.to_string()))?;
+        for chunk in data.chunks(COMPRESSION_CHUNK_SIZE) {
+            let output = encoder.encode(chunk)?;
+            if !output.is_empty() {
+                feed(output);
+                yield_now().await;
+            }
+        }
+    }
+    let output = encoder.finish()?;
+    if !output.is_empty() {
+        feed(output);
+    }
+    Ok(())
+}
+async fn yield_now() {
+    let mut is_yielded = false;
+    std::future::poll_fn(|cx| {
+        if is_yielded {
+            return Poll::Ready(());
+        }
+        is_yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+enum ContentEncoder {
+    Br(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+}
+impl ContentEncoder {
+    fn new(encoding: ContentEncoding) -> Result<Self, AppError> {
+        let encoder = match encoding {
+            ContentEncoding::Br => ContentEncoder::Br(Box::new(brotli::CompressorWriter::new(
+                vec![],
+                COMPRESSION_CHUNK_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_SIZE,
+            ))),
+            ContentEncoding::Zstd => ContentEncoder::Zstd(
+                zstd::stream::write::Encoder::new(vec![], ZSTD_LEVEL)
+                    .map_err(|e| AppError(e.to_string()))?,
+            ),
+            ContentEncoding::Gzip => ContentEncoder::Gzip(flate2::write::GzEncoder::new(
+                vec![],
+                flate2::Compression::default(),
+            )),
+        };
+        Ok(encoder)
+    }
+    // Returns the output which is ready, the encoders keep the rest until more input comes.
+    fn encode(&mut self, data: &[u8]) -> Result<Bytes, AppError> {
+        let output = match self {
+            ContentEncoder::Br(encoder) => {
+                encoder
+                    .write_all(data)
+                    .map_err(|e| AppError(e.to_string()))?;
+                std::mem::take(encoder.get_mut())
+            }
+            ContentEncoder::Zstd(encoder) => {
+                encoder
+                    .write_all(data)
+                    .map_err(|e| AppError(e.to_string()))?;
+                std::mem::take(encoder.get_mut())
+            }
+            ContentEncoder::Gzip(encoder) => {
+                encoder
+                    .write_all(data)
+                    .map_err(|e| AppError(e.to_string()))?;
+                std::mem::take(encoder.get_mut())
+            }
+        };
+        Ok(Bytes::from(output))
+    }
+    fn finish(self) -> Result<Bytes, AppError> {
+        let output = match self {
+            ContentEncoder::Br(encoder) => encoder.into_inner(),
+            ContentEncoder::Zstd(encoder) => {
+                encoder.finish().map_err(|e| AppError(e.to_string()))?
+            }
+            ContentEncoder::Gzip(encoder) => {
+                encoder.finish().map_err(|e| AppError(e.to_string()))?
+            }
+        };
+        Ok(Bytes::from(output))
+    }
+}
+// Returns None if the request body is not encoded with a supported encoding.
+pub fn get_request_encoding(headers: &HeaderMap<HeaderValue>) -> Option<ContentEncoding> {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|item| item.to_str().ok())
+        .and_then(ContentEncoding::from_header)
+}
+// The size of the decoded body is limited, so a small body can not expand without bound, returns
+// None if the decoded body exceeds the max size.
+pub fn decompress_body(
+    encoding: ContentEncoding,
+    body: &[u8],
+    max_size: usize,
+) -> Result<Option<Bytes>, AppError> {
+    let limit = max_size as u64 + 1;
+    let mut output = vec![];
+    let read_result = match encoding {
+        ContentEncoding::Br => brotli::Decompressor::new(body, COMPRESSION_CHUNK_SIZE)
+            .take(limit)
+            .read_to_end(&mut output),
+        ContentEncoding::Zstd => zstd::stream::read::Decoder::new(body)
+            .and_then(|decoder| decoder.take(limit).read_to_end(&mut output)),
+        ContentEncoding::Gzip => flate2::read::MultiGzDecoder::new(body)
+            .take(limit)
+            .read_to_end(&mut output),
+    };
+    read_result.map_err(|e| AppError(format!("Decompress the request body error,{}", e)))?;
+    if output.len() > max_size {
+        return Ok(None);
+    }
+    Ok(Some(Bytes::from(output)))
+}
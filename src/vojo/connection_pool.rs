@@ -0,0 +1,154 @@
+use crate::constants::common_constants::DEFAULT_HTTP2_MAX_CONCURRENT_STREAMS;
+use crate::constants::common_constants::DEFAULT_POOL_IDLE_TIMEOUT_SECONDS;
+use crate::constants::common_constants::DEFAULT_POOL_MAX_IDLE_PER_HOST;
+use monoio_http::h2::client::Builder;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// The upstream connection pool settings of a route cluster, every worker has its own pool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionPoolConfig {
+    #[serde(default = "default_max_idle_per_host")]
+    pub max_idle_per_host: usize,
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: u64,
+    pub max_lifetime_seconds: Option<u64>,
+    pub max_requests_per_connection: Option<u64>,
+}
+fn default_max_idle_per_host() -> usize {
+    DEFAULT_POOL_MAX_IDLE_PER_HOST
+}
+fn default_idle_timeout_seconds() -> u64 {
+    DEFAULT_POOL_IDLE_TIMEOUT_SECONDS
+}
+impl Default for ConnectionPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            idle_timeout_seconds: DEFAULT_POOL_IDLE_TIMEOUT_SECONDS,
+            max_lifetime_seconds: None,
+            max_requests_per_connection: None,
+        }
+    }
+}
+impl ConnectionPoolConfig {
+    pub fn get_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_seconds)
+    }
+    pub fn get_max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime_seconds.map(Duration::from_secs)
+    }
+}
+#[derive(Debug, Default)]
+struct PoolStatsInner {
+    active: AtomicI64,
+    idle: AtomicI64,
+    created: AtomicU64,
+    reused: AtomicU64,
+    closed: AtomicU64,
+}
+// The counters are shared by the pools of all the workers.
+#[derive(Debug, Clone, Default)]
+pub struct PoolStats {
+    inner: Arc<PoolStatsInner>,
+}
+impl PartialEq for PoolStats {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PoolStatsSnapshot {
+    pub active: i64,
+    pub idle: i64,
+    pub created: u64,
+    pub reused: u64,
+    pub closed: u64,
+}
+impl PoolStats {
+    pub fn on_created(&self) {
+        self.inner.created.fetch_add(1, Ordering::Relaxed);
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn on_reused(&self) {
+        self.inner.reused.fetch_add(1, Ordering::Relaxed);
+        self.inner.idle.fetch_sub(1, Ordering::Relaxed);
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+    }
+    // A stream of an http2 connection is opened.
+    pub fn on_multiplexed(&self) {
+        self.inner.reused.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn on_released(&self) {
+        self.inner.active.fetch_sub(1, Ordering::Relaxed);
+        self.inner.idle.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn on_closed(&self, is_idle: bool) {
+        if is_idle {
+            self.inner.idle.fetch_sub(1, Ordering::Relaxed);
+        } else {
+            self.inner.active.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.inner.closed.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn get_snapshot(&self) -> PoolStatsSnapshot {
+        PoolStatsSnapshot {
+            active: self.inner.active.load(Ordering::Relaxed),
+            idle: self.inner.idle.load(Ordering::Relaxed),
+            created: self.inner.created.load(Ordering::Relaxed),
+            reused: self.inner.reused.load(Ordering::Relaxed),
+            closed: self.inner.closed.load(Ordering::Relaxed),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum UpstreamProtocol {
+    #[default]
+    #[serde(rename = "http1")]
+    Http1,
+    // Http2 over the cleartext with the prior knowledge.
+    #[serde(rename = "h2c")]
+    H2c,
+    // Http2 over the tls, negotiated with the alpn.
+    #[serde(rename = "h2")]
+    H2,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Http2Config {
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub max_frame_size: Option<u32>,
+    // A new connection is opened when all the connections have so many streams.
+    #[serde(default = "default_max_concurrent_streams")]
+    pub max_concurrent_streams: usize,
+}
+fn default_max_concurrent_streams() -> usize {
+    DEFAULT_HTTP2_MAX_CONCURRENT_STREAMS
+}
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            max_frame_size: None,
+            max_concurrent_streams: DEFAULT_HTTP2_MAX_CONCURRENT_STREAMS,
+        }
+    }
+}
+impl Http2Config {
+    pub fn get_builder(&self) -> Builder {
+        let mut builder = Builder::new();
+        if let Some(initial_stream_window_size) = self.initial_stream_window_size {
+            builder.initial_window_size(initial_stream_window_size);
+        }
+        if let Some(initial_connection_window_size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(initial_connection_window_size);
+        }
+        if let Some(max_frame_size) = self.max_frame_size {
+            builder.max_frame_size(max_frame_size);
+        }
+        builder
+    }
+}
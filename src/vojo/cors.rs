@@ -0,0 +1,209 @@
+use crate::ensure;
+use crate::vojo::app_config::get_cached_regex;
+use crate::vojo::app_error::AppError;
+use crate::vojo::header_operations::append_vary;
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use http::request::Parts;
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+
+fn default_allow_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST"]
+        .iter()
+        .map(|item| item.to_string())
+        .collect()
+}
+// The "*" in the origins, the methods and the headers allows all of them, the requested methods and
+// headers are echoed back. The "*" origin can not be used with the credentials, it would allow every
+// site to send the credentialed requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorsConfig {
+    #[serde(default)]
+    pub allow_origins: Vec<String>,
+    // Matched against the whole origin, e.g. "https://.*\\.example\\.com".
+    #[serde(default)]
+    pub allow_origin_regexes: Vec<String>,
+    #[serde(default = "default_allow_methods")]
+    pub allow_methods: Vec<String>,
+    #[serde(default)]
+    pub allow_headers: Vec<String>,
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    pub max_age_seconds: Option<u64>,
+}
+impl CorsConfig {
+    pub fn get_regex_patterns(&self) -> Vec<String> {
+        self.allow_origin_regexes
+            .iter()
+            .map(|item| format!("^(?:{})$", item))
+            .collect()
+    }
+    pub fn check(&self) -> Result<(), AppError> {
+        ensure!(
+            !self.allow_credentials || !self.allow_origins.iter().any(|item| item == "*"),
+            "The allow_origins \"*\" can not be used with the allow_credentials"
+        );
+        for pattern in self.get_regex_patterns() {
+            get_cached_regex(&pattern)?;
+        }
+        Ok(())
+    }
+    fn get_allow_origin(&self, origin: &HeaderValue) -> Result<Option<HeaderValue>, AppError> {
+        let Ok(origin_str) = origin.to_str() else {
+            return Ok(None);
+        };
+        if self.allow_origins.iter().any(|item| item == "*") {
+            return Ok(Some(HeaderValue::from_static("*")));
+        }
+        if self
+            .allow_origins
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(origin_str))
+        {
+            return Ok(Some(origin.clone()));
+        }
+        for pattern in self.get_regex_patterns() {
+            if get_cached_regex(&pattern)?.is_match(origin_str) {
+                return Ok(Some(origin.clone()));
+            }
+        }
+        Ok(None)
+    }
+    fn is_method_allowed(&self, method: &str) -> bool {
+        self.allow_methods
+            .iter()
+            .any(|item| item == "*" || item.eq_ignore_ascii_case(method))
+    }
+    // The browsers only request the headers which are not safelisted.
+    fn is_headers_allowed(&self, request_headers: &str) -> bool {
+        if self.allow_headers.iter().any(|item| item == "*") {
+            return true;
+        }
+        request_headers
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .all(|header| {
+                self.allow_headers
+                    .iter()
+                    .any(|item| item.eq_ignore_ascii_case(header))
+            })
+    }
+    // Returns None if the request is not allowed, the response is sent without the cors headers.
+    pub fn get_preflight_headers(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Option<HeaderMap<HeaderValue>>, AppError> {
+        let Some(origin) = headers.get(ORIGIN) else {
+            return Ok(None);
+        };
+        let Some(allow_origin) = self.get_allow_origin(origin)? else {
+            return Ok(None);
+        };
+        let request_method = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|item| item.to_str().ok())
+            .unwrap_or_default();
+        let request_headers = headers
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|item| item.to_str().ok())
+            .unwrap_or_default();
+        if !self.is_method_allowed(request_method) || !self.is_headers_allowed(request_headers) {
+            return Ok(None);
+        }
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(
+            VARY,
+            HeaderValue::from_static(
+                "origin, access-control-request-method, access-control-request-headers",
+            ),
+        );
+        response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        let allow_methods = if self.allow_methods.iter().any(|item| item == "*") {
+            request_method.to_string()
+        } else {
+            self.allow_methods.join(", ")
+        };
+        response_headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_str(&allow_methods).map_err(|e| AppError(e.to_string()))?,
+        );
+        let allow_headers = if self.allow_headers.iter().any(|item| item == "*") {
+            request_headers.to_string()
+        } else {
+            self.allow_headers.join(", ")
+        };
+        if !allow_headers.is_empty() {
+            response_headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_str(&allow_headers).map_err(|e| AppError(e.to_string()))?,
+            );
+        }
+        if self.allow_credentials {
+            response_headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if let Some(max_age_seconds) = self.max_age_seconds {
+            response_headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age_seconds));
+        }
+        Ok(Some(response_headers))
+    }
+    // The cors headers of the upstream are replaced by the policy of the route, they are removed
+    // even if the origin is not allowed.
+    pub fn apply_response_headers(
+        &self,
+        request_headers: &HeaderMap<HeaderValue>,
+        response_headers: &mut HeaderMap<HeaderValue>,
+    ) -> Result<(), AppError> {
+        let upstream_cors_headers: Vec<HeaderName> = response_headers
+            .keys()
+            .filter(|item| item.as_str().starts_with("access-control-"))
+            .cloned()
+            .collect();
+        for key in upstream_cors_headers {
+            response_headers.remove(key);
+        }
+        append_vary(response_headers, "origin");
+        let Some(allow_origin) = request_headers
+            .get(ORIGIN)
+            .map(|item| self.get_allow_origin(item))
+            .transpose()?
+            .flatten()
+        else {
+            return Ok(());
+        };
+        response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.allow_credentials {
+            response_headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.expose_headers.is_empty() {
+            response_headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_str(&self.expose_headers.join(", "))
+                    .map_err(|e| AppError(e.to_string()))?,
+            );
+        }
+        Ok(())
+    }
+}
+// The OPTIONS request with the origin and the requested method, returns the requested method.
+pub fn get_preflight_method(parts: &Parts) -> Option<Method> {
+    if parts.method != Method::OPTIONS || !parts.headers.contains_key(ORIGIN) {
+        return None;
+    }
+    parts
+        .headers
+        .get(ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|item| Method::from_bytes(item.as_bytes()).ok())
+}
@@ -0,0 +1,407 @@
+use crate::constants::common_constants::DEFAULT_DISCOVERY_REFRESH_SECONDS;
+use crate::constants::common_constants::DEFAULT_HTTP_POLL_TIMEOUT_MILLIS;
+use crate::discovery::dns_resolver::get_nameserver;
+use crate::discovery::dns_resolver::query;
+use crate::discovery::dns_resolver::DnsRecord;
+use crate::discovery::dns_resolver::RECORD_TYPE_A;
+use crate::discovery::dns_resolver::RECORD_TYPE_AAAA;
+use crate::discovery::dns_resolver::RECORD_TYPE_SRV;
+use crate::vojo::app_error::AppError;
+use crate::vojo::route::DiscoveredEndpoint;
+use crate::vojo::route::Locality;
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::fmt::Debug;
+use dyn_clone::DynClone;
+use http::header::{ACCEPT, HOST};
+use http::Uri;
+use http_body_util::{BodyExt, Empty};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+#[typetag::serde(tag = "type")]
+#[async_trait]
+pub trait DiscoveryProvider: Sync + Send + DynClone {
+    async fn get_endpoints(&self) -> Result<Vec<DiscoveredEndpoint>, AppError>;
+
+    fn get_refresh_interval(&self) -> Duration;
+    // The file whose changes trigger a refresh before the interval is due.
+    fn get_watch_path(&self) -> Option<PathBuf> {
+        None
+    }
+    fn get_debug(&self) -> String {
+        String::from("debug")
+    }
+    fn as_any(&self) -> &dyn Any;
+}
+dyn_clone::clone_trait_object!(DiscoveryProvider);
+
+impl Debug for dyn DiscoveryProvider {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let routes = self.get_debug();
+        write!(f, "{{{}}}", routes)
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum DnsRecordType {
+    // Both of the A and the AAAA records.
+    #[default]
+    #[serde(rename = "A")]
+    Address,
+    #[serde(rename = "SRV")]
+    Srv,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsDiscoveryProvider {
+    pub domain_name: String,
+    #[serde(default)]
+    pub record_type: DnsRecordType,
+    pub port: Option<u16>,
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    pub nameserver: Option<String>,
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+}
+fn default_scheme() -> String {
+    String::from("http")
+}
+fn default_refresh_seconds() -> u64 {
+    DEFAULT_DISCOVERY_REFRESH_SECONDS
+}
+#[typetag::serde(name = "Dns")]
+#[async_trait]
+impl DiscoveryProvider for DnsDiscoveryProvider {
+    async fn get_endpoints(&self) -> Result<Vec<DiscoveredEndpoint>, AppError> {
+        let nameserver = get_nameserver(self.nameserver.clone())?;
+        match self.record_type {
+            DnsRecordType::Address => {
+                let port = self.port.ok_or(AppError(String::from(
+                    "The port counld not be none for the A records",
+                )))?;
+                let mut endpoints = vec![];
+                for record_type in [RECORD_TYPE_A, RECORD_TYPE_AAAA] {
+                    let response = query(nameserver, &self.domain_name, record_type).await?;
+                    for record in response.answers {
+                        if let DnsRecord::Address(_, ip) = record {
+                            endpoints.push(DiscoveredEndpoint {
+                                endpoint: self.get_endpoint(&get_host(ip), port),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+                Ok(endpoints)
+            }
+            DnsRecordType::Srv => {
+                let response = query(nameserver, &self.domain_name, RECORD_TYPE_SRV).await?;
+                let mut endpoints = vec![];
+                for record in response.answers.iter() {
+                    let DnsRecord::Srv(srv_record) = record else {
+                        continue;
+                    };
+                    // Use the address in the additional section if the server has sent it.
+                    let hosts: Vec<String> = response
+                        .additionals
+                        .iter()
+                        .filter_map(|item| match item {
+                            DnsRecord::Address(name, ip) if *name == srv_record.target => {
+                                Some(get_host(*ip))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    let hosts = if hosts.is_empty() {
+                        vec![srv_record.target.clone()]
+                    } else {
+                        hosts
+                    };
+                    for host in hosts {
+                        endpoints.push(DiscoveredEndpoint {
+                            endpoint: self.get_endpoint(&host, srv_record.port),
+                            weight: Some((srv_record.weight as u64).max(1)),
+                            priority: srv_record.priority as u32,
+                            ..Default::default()
+                        });
+                    }
+                }
+                Ok(endpoints)
+            }
+        }
+    }
+    fn get_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_seconds.max(1))
+    }
+    fn get_debug(&self) -> String {
+        format!("{:?}", self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+impl DnsDiscoveryProvider {
+    fn get_endpoint(&self, host: &str, port: u16) -> String {
+        format!("{}://{}:{}", self.scheme, host, port)
+    }
+}
+fn get_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+// The file is a list of the endpoints, in json if the name ends with .json, otherwise in yaml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDiscoveryProvider {
+    pub path: String,
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+}
+#[typetag::serde(name = "File")]
+#[async_trait]
+impl DiscoveryProvider for FileDiscoveryProvider {
+    async fn get_endpoints(&self) -> Result<Vec<DiscoveredEndpoint>, AppError> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| AppError(format!("Read the file {} error,{}", self.path, e)))?;
+        if self.path.ends_with(".json") {
+            serde_json::from_str(&content)
+                .map_err(|e| AppError(format!("Parse the file {} error,{}", self.path, e)))
+        } else {
+            serde_yaml::from_str(&content)
+                .map_err(|e| AppError(format!("Parse the file {} error,{}", self.path, e)))
+        }
+    }
+    fn get_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_seconds.max(1))
+    }
+    fn get_watch_path(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.path))
+    }
+    fn get_debug(&self) -> String {
+        format!("{:?}", self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub enum HttpPollFormat {
+    // The response of /v1/catalog/service/<name> or /v1/health/service/<name>?passing.
+    #[default]
+    Consul,
+    // A v1 Endpoints object of the Kubernetes api.
+    Kubernetes,
+}
+// Polls a document in the shape of the Consul catalog or the Kubernetes Endpoints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpPollDiscoveryProvider {
+    pub url: String,
+    #[serde(default)]
+    pub format: HttpPollFormat,
+    // The named port of the Endpoints, the first port is used if it is none.
+    pub port_name: Option<String>,
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default = "default_refresh_seconds")]
+    pub refresh_seconds: u64,
+}
+#[typetag::serde(name = "HttpPoll")]
+#[async_trait]
+impl DiscoveryProvider for HttpPollDiscoveryProvider {
+    async fn get_endpoints(&self) -> Result<Vec<DiscoveredEndpoint>, AppError> {
+        let body = self.fetch().await?;
+        match self.format {
+            HttpPollFormat::Consul => self.parse_consul(&body),
+            HttpPollFormat::Kubernetes => self.parse_kubernetes(&body),
+        }
+    }
+    fn get_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_seconds.max(1))
+    }
+    fn get_debug(&self) -> String {
+        format!("{:?}", self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+impl HttpPollDiscoveryProvider {
+    async fn fetch(&self) -> Result<Bytes, AppError> {
+        let uri = self
+            .url
+            .parse::<Uri>()
+            .map_err(|e| AppError(format!("Invalid url {},{}", self.url, e)))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(AppError(format!(
+                "Only the http url is supported,{}",
+                self.url
+            )));
+        }
+        let host = uri
+            .host()
+            .ok_or(AppError(format!("Invalid url {}", self.url)))?
+            .to_string();
+        let port = uri.port_u16().unwrap_or(80);
+        let timeout = Duration::from_millis(DEFAULT_HTTP_POLL_TIMEOUT_MILLIS);
+        let fetch_future = async {
+            let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+                .await
+                .map_err(|e| AppError(format!("Connect to {} error,{}", self.url, e)))?;
+            let (mut sender, connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                    .await
+                    .map_err(|e| AppError(e.to_string()))?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    debug!("The connection of the http poll is closed,{}", e);
+                }
+            });
+            let path_and_query = uri
+                .path_and_query()
+                .map_or("/", |item| item.as_str())
+                .to_string();
+            let mut request_builder = http::Request::builder()
+                .uri(path_and_query)
+                .header(
+                    HOST,
+                    uri.authority().map_or(host.as_str(), |item| item.as_str()),
+                )
+                .header(ACCEPT, "application/json");
+            for (key, value) in self.headers.iter().flatten() {
+                request_builder = request_builder.header(key, value);
+            }
+            let request = request_builder
+                .body(Empty::<Bytes>::new())
+                .map_err(|e| AppError(e.to_string()))?;
+            let response = sender
+                .send_request(request)
+                .await
+                .map_err(|e| AppError(e.to_string()))?;
+            if !response.status().is_success() {
+                return Err(AppError(format!(
+                    "The status of {} is {}",
+                    self.url,
+                    response.status()
+                )));
+            }
+            let body = response
+                .into_body()
+                .collect()
+                .await
+                .map_err(|e| AppError(e.to_string()))?
+                .to_bytes();
+            Ok(body)
+        };
+        tokio::time::timeout(timeout, fetch_future)
+            .await
+            .map_err(|_| AppError(format!("Poll {} timed out", self.url)))?
+    }
+    fn parse_consul(&self, body: &[u8]) -> Result<Vec<DiscoveredEndpoint>, AppError> {
+        let services: Vec<Value> =
+            serde_json::from_slice(body).map_err(|e| AppError(e.to_string()))?;
+        let mut endpoints = vec![];
+        for item in services.iter() {
+            // The health api nests the catalog fields into the Node and the Service.
+            let (node, service) = match (item.get("Node"), item.get("Service")) {
+                (Some(node), Some(service)) if node.is_object() => (node, service),
+                _ => (item, item),
+            };
+            let service_address = service
+                .get("ServiceAddress")
+                .or(service.get("Address"))
+                .and_then(Value::as_str)
+                .filter(|item| !item.is_empty());
+            let Some(address) = service_address.or(node.get("Address").and_then(Value::as_str))
+            else {
+                continue;
+            };
+            let Some(port) = service
+                .get("ServicePort")
+                .or(service.get("Port"))
+                .and_then(Value::as_u64)
+            else {
+                continue;
+            };
+            let weight = service
+                .get("ServiceWeights")
+                .or(service.get("Weights"))
+                .and_then(|item| item.get("Passing"))
+                .and_then(Value::as_u64);
+            let region = node
+                .get("Datacenter")
+                .and_then(Value::as_str)
+                .map(|item| item.to_string());
+            endpoints.push(DiscoveredEndpoint {
+                endpoint: self.get_endpoint(address, port),
+                weight: weight.map(|item| item.max(1)),
+                locality: region.map(|region| Locality {
+                    zone: None,
+                    region: Some(region),
+                }),
+                ..Default::default()
+            });
+        }
+        Ok(endpoints)
+    }
+    fn parse_kubernetes(&self, body: &[u8]) -> Result<Vec<DiscoveredEndpoint>, AppError> {
+        let endpoints_object: Value =
+            serde_json::from_slice(body).map_err(|e| AppError(e.to_string()))?;
+        let mut endpoints = vec![];
+        let subsets = endpoints_object
+            .get("subsets")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        for subset in subsets.iter() {
+            let ports = subset
+                .get("ports")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            let port_option = ports
+                .iter()
+                .find(|item| match &self.port_name {
+                    Some(port_name) => {
+                        item.get("name").and_then(Value::as_str) == Some(port_name.as_str())
+                    }
+                    None => true,
+                })
+                .and_then(|item| item.get("port"))
+                .and_then(Value::as_u64);
+            let Some(port) = port_option else {
+                continue;
+            };
+            // The notReadyAddresses are skipped.
+            let addresses = subset
+                .get("addresses")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            for address in addresses.iter() {
+                let Some(ip) = address.get("ip").and_then(Value::as_str) else {
+                    continue;
+                };
+                endpoints.push(DiscoveredEndpoint {
+                    endpoint: self.get_endpoint(ip, port),
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(endpoints)
+    }
+    fn get_endpoint(&self, address: &str, port: u64) -> String {
+        match address.parse::<IpAddr>() {
+            Ok(ip) => format!("{}://{}:{}", self.scheme, get_host(ip), port),
+            Err(_) => format!("{}://{}:{}", self.scheme, address, port),
+        }
+    }
+}
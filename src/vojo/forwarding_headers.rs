@@ -0,0 +1,158 @@
+use crate::vojo::allow_deny_ip::ip_is_in_cidrs;
+use crate::vojo::app_error::AppError;
+use http::header::{FORWARDED, HOST};
+use http::HeaderMap;
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+static X_FORWARDED_FOR: &str = "x-forwarded-for";
+static X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+static X_FORWARDED_HOST: &str = "x-forwarded-host";
+static X_FORWARDED_PORT: &str = "x-forwarded-port";
+static X_REAL_IP: &str = "x-real-ip";
+
+fn default_true() -> bool {
+    true
+}
+// The forwarding headers of the clients which are not trusted proxies are removed, the headers
+// of the trusted proxies are kept and the client ip is appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForwardingHeadersConfig {
+    #[serde(default = "default_true")]
+    pub x_forwarded_for: bool,
+    #[serde(default = "default_true")]
+    pub x_forwarded_proto: bool,
+    #[serde(default = "default_true")]
+    pub x_forwarded_host: bool,
+    #[serde(default = "default_true")]
+    pub x_forwarded_port: bool,
+    #[serde(default = "default_true")]
+    pub x_real_ip: bool,
+    // The RFC 7239 header.
+    #[serde(default)]
+    pub forwarded: bool,
+    // The ips or cidrs, e.g. "10.0.0.0/8".
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+impl Default for ForwardingHeadersConfig {
+    fn default() -> Self {
+        Self {
+            x_forwarded_for: true,
+            x_forwarded_proto: true,
+            x_forwarded_host: true,
+            x_forwarded_port: true,
+            x_real_ip: true,
+            forwarded: false,
+            trusted_proxies: vec![],
+        }
+    }
+}
+impl ForwardingHeadersConfig {
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        ip_is_in_cidrs(ip, &self.trusted_proxies)
+    }
+    // The right-most ip of the chain which is not a trusted proxy.
+    fn get_client_ip(&self, forwarded_for: &str, remote_ip: &str) -> String {
+        let mut client_ip = remote_ip.to_string();
+        for item in forwarded_for.rsplit(',').map(|item| item.trim()) {
+            let Ok(ip) = item.parse::<IpAddr>() else {
+                break;
+            };
+            client_ip = item.to_string();
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+        client_ip
+    }
+    pub fn apply(
+        &self,
+        headers: &mut HeaderMap<HeaderValue>,
+        remote_ip: &str,
+        proto: &str,
+        port: i32,
+    ) -> Result<(), AppError> {
+        let is_trusted = remote_ip
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| self.is_trusted(&ip));
+        if !is_trusted {
+            for key in [
+                X_FORWARDED_FOR,
+                X_FORWARDED_PROTO,
+                X_FORWARDED_HOST,
+                X_FORWARDED_PORT,
+                X_REAL_IP,
+                FORWARDED.as_str(),
+            ] {
+                headers.remove(key);
+            }
+        }
+        let host = headers
+            .get(HOST)
+            .and_then(|item| item.to_str().ok())
+            .map(|item| item.to_string());
+        let forwarded_for = get_joined_value(headers, X_FORWARDED_FOR);
+        if self.x_real_ip {
+            let client_ip = self.get_client_ip(&forwarded_for, remote_ip);
+            headers.insert(X_REAL_IP, get_header_value(&client_ip)?);
+        }
+        if self.x_forwarded_for {
+            let value = if forwarded_for.is_empty() {
+                remote_ip.to_string()
+            } else {
+                format!("{}, {}", forwarded_for, remote_ip)
+            };
+            headers.insert(X_FORWARDED_FOR, get_header_value(&value)?);
+        }
+        if self.x_forwarded_proto && !headers.contains_key(X_FORWARDED_PROTO) {
+            headers.insert(X_FORWARDED_PROTO, get_header_value(proto)?);
+        }
+        if let (true, false, Some(host)) = (
+            self.x_forwarded_host,
+            headers.contains_key(X_FORWARDED_HOST),
+            &host,
+        ) {
+            headers.insert(X_FORWARDED_HOST, get_header_value(host)?);
+        }
+        if self.x_forwarded_port && !headers.contains_key(X_FORWARDED_PORT) {
+            headers.insert(X_FORWARDED_PORT, get_header_value(&port.to_string())?);
+        }
+        if self.forwarded {
+            let mut element = format!("for={}", get_forwarded_node(remote_ip));
+            if let Some(host) = &host {
+                element.push_str(&format!(";host=\"{}\"", host.replace('"', "")));
+            }
+            element.push_str(&format!(";proto={}", proto));
+            let forwarded = get_joined_value(headers, FORWARDED.as_str());
+            let value = if forwarded.is_empty() {
+                element
+            } else {
+                format!("{}, {}", forwarded, element)
+            };
+            headers.insert(FORWARDED, get_header_value(&value)?);
+        }
+        Ok(())
+    }
+}
+// The header may be sent in several lines.
+fn get_joined_value(headers: &HeaderMap<HeaderValue>, key: &str) -> String {
+    headers
+        .get_all(key)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+// The ipv6 address is quoted and enclosed in the square brackets.
+fn get_forwarded_node(ip: &str) -> String {
+    if ip.contains(':') {
+        format!("\"[{}]\"", ip)
+    } else {
+        ip.to_string()
+    }
+}
+fn get_header_value(value: &str) -> Result<HeaderValue, AppError> {
+    HeaderValue::from_str(value).map_err(|e| AppError(e.to_string()))
+}
@@ -1,15 +1,17 @@
 use super::handler::Handler;
+use crate::proxy::http_cache::HttpCache;
+use crate::proxy::upstream_pool::UpstreamPool;
 use crate::vojo::thread_local_info::ThreadLocalInfo;
 use crate::AppError;
 use monoio_http::common::request::Request;
-use monoio_http_client::Client;
 use std::sync::Arc;
 use std::sync::Mutex;
 pub struct GatewayRequest {
     pub port: i32,
     pub request: Request,
     pub remote_ip: String,
-    pub client: Client,
+    pub upstream_pool: UpstreamPool,
+    pub http_cache: HttpCache,
     pub handler: Handler,
     pub thread_local_info_mutex: Arc<Mutex<ThreadLocalInfo>>,
 }
@@ -18,7 +20,8 @@ impl GatewayRequest {
         port: i32,
         request: Request,
         remote_ip: String,
-        client: Client,
+        upstream_pool: UpstreamPool,
+        http_cache: HttpCache,
         handler: Handler,
         thread_local_info_mutex: Arc<Mutex<ThreadLocalInfo>>,
     ) -> Self {
@@ -26,7 +29,8 @@ impl GatewayRequest {
             port,
             request,
             remote_ip,
-            client,
+            upstream_pool,
+            http_cache,
             handler,
             thread_local_info_mutex,
         }
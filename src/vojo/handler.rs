@@ -1,4 +1,5 @@
 use crate::vojo::app_config::AppConfig;
+use crate::vojo::cache_config::CachePurgeLog;
 use futures::channel::oneshot::Sender;
 use std::collections::HashMap;
 use std::sync::mpsc;
@@ -9,12 +10,14 @@ use std::sync::RwLock;
 pub struct Handler {
     pub shared_app_config: Arc<RwLock<AppConfig>>,
     pub senders: Arc<Mutex<HashMap<i32, Vec<Sender<i32>>>>>,
+    pub cache_purge_log: CachePurgeLog,
 }
 impl Handler {
     pub fn new() -> Self {
         Self {
             shared_app_config: Arc::new(RwLock::new(Default::default())),
             senders: Arc::new(Mutex::new(HashMap::new())),
+            cache_purge_log: CachePurgeLog::default(),
         }
     }
 }
@@ -0,0 +1,111 @@
+use crate::ensure;
+use crate::vojo::app_error::AppError;
+use http::header::HeaderName;
+use http::header::VARY;
+use http::HeaderMap;
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const TEMPLATE_VARIABLES: [&str; 4] = ["remote_ip", "route_id", "upstream", "request_id"];
+// The headers are removed first, then set, then appended. The values may contain the variables
+// ${remote_ip}, ${route_id}, ${upstream} and ${request_id}.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct HeaderOperations {
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    #[serde(default)]
+    pub append: HashMap<String, String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+impl HeaderOperations {
+    pub fn apply(
+        &self,
+        headers: &mut HeaderMap<HeaderValue>,
+        context: &HeaderTemplateContext,
+    ) -> Result<(), AppError> {
+        for key in self.remove.iter() {
+            headers.remove(key.as_str());
+        }
+        for (key, value) in self.set.iter() {
+            headers.insert(get_header_name(key)?, context.get_header_value(value)?);
+        }
+        for (key, value) in self.append.iter() {
+            headers.append(get_header_name(key)?, context.get_header_value(value)?);
+        }
+        Ok(())
+    }
+    pub fn check(&self) -> Result<(), AppError> {
+        for key in self.remove.iter() {
+            get_header_name(key)?;
+        }
+        for (key, value) in self.set.iter().chain(self.append.iter()) {
+            check_header(key, value)?;
+        }
+        Ok(())
+    }
+}
+// The value is checked with the variables removed, the values of the variables are always valid.
+pub fn check_header(key: &str, template: &str) -> Result<(), AppError> {
+    get_header_name(key)?;
+    let mut value = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        value.push_str(&rest[..start]);
+        let (variable, next) = rest[start + 2..].split_once('}').ok_or(AppError(format!(
+            "The variable of the header {} is not closed",
+            key
+        )))?;
+        ensure!(
+            TEMPLATE_VARIABLES.contains(&variable),
+            format!("Unknown variable {} in the header {}", variable, key)
+        );
+        rest = next;
+    }
+    value.push_str(rest);
+    HeaderValue::from_str(&value)
+        .map_err(|e| AppError(format!("Invalid value of the header {},{}", key, e)))?;
+    Ok(())
+}
+pub fn get_header_name(key: &str) -> Result<HeaderName, AppError> {
+    HeaderName::from_bytes(key.as_bytes()).map_err(|e| AppError(e.to_string()))
+}
+#[derive(Debug, Clone, Default)]
+pub struct HeaderTemplateContext {
+    pub remote_ip: String,
+    pub route_id: String,
+    pub upstream: String,
+    pub request_id: String,
+}
+impl HeaderTemplateContext {
+    // The templates are checked when the route table is built.
+    pub fn render(&self, template: &str) -> String {
+        if !template.contains("${") {
+            return template.to_string();
+        }
+        template
+            .replace("${remote_ip}", &self.remote_ip)
+            .replace("${route_id}", &self.route_id)
+            .replace("${upstream}", &self.upstream)
+            .replace("${request_id}", &self.request_id)
+    }
+    pub fn get_header_value(&self, template: &str) -> Result<HeaderValue, AppError> {
+        HeaderValue::from_str(&self.render(template)).map_err(|e| AppError(e.to_string()))
+    }
+}
+// The name is not appended if the Vary already has it or is "*".
+pub fn append_vary(headers: &mut HeaderMap<HeaderValue>, name: &'static str) {
+    let has_name = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .flat_map(|item| item.split(','))
+        .any(|item| {
+            let item = item.trim();
+            item == "*" || item.eq_ignore_ascii_case(name)
+        });
+    if !has_name {
+        headers.append(VARY, HeaderValue::from_static(name));
+    }
+}
@@ -3,8 +3,20 @@ pub mod app_config;
 pub mod app_error;
 pub mod authentication;
 pub mod base_response;
+pub mod cache_config;
+pub mod compression;
+pub mod connection_pool;
+pub mod cors;
+pub mod discovery_provider;
+pub mod forwarding_headers;
 pub mod gateway_request;
 pub mod handler;
+pub mod header_operations;
 pub mod rate_limit;
 pub mod route;
+pub mod route_action;
+#[cfg(test)]
+mod route_match_bench;
+pub mod route_table;
+pub mod size_limits;
 pub mod thread_local_info;
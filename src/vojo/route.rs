@@ -1,13 +1,23 @@
+use super::app_config::SlowStartConfig;
 use super::app_error::AppError;
+use crate::constants::common_constants::DEFAULT_HASH_RING_VIRTUAL_NODES;
+use crate::constants::common_constants::DEFAULT_PEAK_EWMA_DECAY_MILLIS;
+use crate::constants::common_constants::DEFAULT_PEAK_EWMA_RTT_MILLIS;
+use crate::constants::common_constants::SLOW_START_FAILURE_THRESHOLD;
+use crate::constants::common_constants::SLOW_START_WEIGHT_SCALE;
 
 use core::fmt::Debug;
 use http::HeaderMap;
 use http::HeaderValue;
+use http::Uri;
 use rand::thread_rng;
 use rand::Rng;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use tracing::metadata::LevelFilter;
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -18,21 +28,69 @@ pub enum LoadbalancerStrategy {
     HeaderRoute(HeaderRoute),
     RandomRoute(RandomRoute),
     WeightRoute(WeightRoute),
+    LeastConnection(LeastConnectionRoute),
+    PeakEwma(PeakEwmaRoute),
+    HashRoute(HashRoute),
+    LocalityRoute(LocalityRoute),
+}
+// The routes with an action have no route cluster.
+impl Default for LoadbalancerStrategy {
+    fn default() -> Self {
+        LoadbalancerStrategy::PollRoute(PollRoute::default())
+    }
 }
 
 impl LoadbalancerStrategy {
-    pub async fn get_route(
-        &mut self,
+    // The slow start scales the weight of a route, the strategies which pick the route by the
+    // request or in turn have no weight to scale.
+    pub fn supports_slow_start(&self) -> bool {
+        !matches!(
+            self,
+            LoadbalancerStrategy::PollRoute(_)
+                | LoadbalancerStrategy::HashRoute(_)
+                | LoadbalancerStrategy::HeaderRoute(_)
+        )
+    }
+    pub fn get_route(
+        &self,
         headers: HeaderMap<HeaderValue>,
+        remote_ip: String,
+        uri: Uri,
+        local_locality: Option<Locality>,
+        min_liveness_count: i32,
+        slow_start_config: Option<&SlowStartConfig>,
     ) -> Result<BaseRoute, AppError> {
         match self {
-            LoadbalancerStrategy::PollRoute(poll_route) => poll_route.get_route(headers).await,
+            LoadbalancerStrategy::PollRoute(poll_route) => poll_route.get_route(headers),
+
+            LoadbalancerStrategy::HeaderRoute(poll_route) => poll_route.get_route(headers),
+
+            LoadbalancerStrategy::RandomRoute(poll_route) => {
+                poll_route.get_route(headers, slow_start_config)
+            }
 
-            LoadbalancerStrategy::HeaderRoute(poll_route) => poll_route.get_route(headers).await,
+            LoadbalancerStrategy::WeightRoute(poll_route) => {
+                poll_route.get_route(headers, slow_start_config)
+            }
+
+            LoadbalancerStrategy::LeastConnection(poll_route) => {
+                poll_route.get_route(headers, slow_start_config)
+            }
 
-            LoadbalancerStrategy::RandomRoute(poll_route) => poll_route.get_route(headers).await,
+            LoadbalancerStrategy::PeakEwma(poll_route) => {
+                poll_route.get_route(headers, slow_start_config)
+            }
 
-            LoadbalancerStrategy::WeightRoute(poll_route) => poll_route.get_route(headers).await,
+            LoadbalancerStrategy::HashRoute(poll_route) => {
+                poll_route.get_route(headers, remote_ip, uri)
+            }
+
+            LoadbalancerStrategy::LocalityRoute(poll_route) => poll_route.get_route(
+                headers,
+                local_locality,
+                min_liveness_count,
+                slow_start_config,
+            ),
         }
     }
     pub fn get_all_route(&mut self) -> Result<Vec<&mut BaseRoute>, AppError> {
@@ -43,32 +101,441 @@ impl LoadbalancerStrategy {
             LoadbalancerStrategy::RandomRoute(poll_route) => poll_route.get_all_route(),
 
             LoadbalancerStrategy::WeightRoute(poll_route) => poll_route.get_all_route(),
+
+            LoadbalancerStrategy::LeastConnection(poll_route) => poll_route.get_all_route(),
+
+            LoadbalancerStrategy::PeakEwma(poll_route) => poll_route.get_all_route(),
+
+            LoadbalancerStrategy::HashRoute(poll_route) => poll_route.get_all_route(),
+
+            LoadbalancerStrategy::LocalityRoute(poll_route) => poll_route.get_all_route(),
+        }
+    }
+    pub fn get_base_routes(&self) -> Vec<&BaseRoute> {
+        match self {
+            LoadbalancerStrategy::PollRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::HeaderRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::RandomRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::WeightRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::LeastConnection(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::PeakEwma(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::HashRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+            LoadbalancerStrategy::LocalityRoute(poll_route) => poll_route
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .collect(),
+        }
+    }
+    // Keep the runtime state of the routes which are still in the new cluster,
+    // so updating one route through the admin api does not reset the others.
+    pub fn update_cluster(
+        &mut self,
+        mut new_cluster: LoadbalancerStrategy,
+    ) -> Result<(), AppError> {
+        for new_route in new_cluster.get_all_route()? {
+            if let Some(older_route) = self
+                .get_all_route()?
+                .into_iter()
+                .find(|item| item.endpoint == new_route.endpoint)
+            {
+                new_route.base_route_id = older_route.base_route_id.clone();
+                new_route.is_alive = older_route.is_alive;
+                new_route.anomaly_detection_status = older_route.anomaly_detection_status.clone();
+                new_route.load_status = older_route.load_status.clone();
+            } else {
+                new_route.load_status.start_warm_up();
+            }
+        }
+        if let (
+            LoadbalancerStrategy::WeightRoute(older_cluster),
+            LoadbalancerStrategy::WeightRoute(new_weight_cluster),
+        ) = (&*self, &mut new_cluster)
+        {
+            for item in new_weight_cluster.routes.iter_mut() {
+                if let Some(older_item) = older_cluster
+                    .routes
+                    .iter()
+                    .find(|older| older.base_route.endpoint == item.base_route.endpoint)
+                {
+                    item.current_weight = older_item.current_weight.clone();
+                }
+            }
         }
+        *self = new_cluster;
+        Ok(())
+    }
+    // Replace the routes with the discovered endpoints, the unchanged ones keep their state.
+    pub fn update_endpoints(&mut self, endpoints: Vec<DiscoveredEndpoint>) -> Result<(), AppError> {
+        let mut new_cluster = self.clone();
+        match &mut new_cluster {
+            LoadbalancerStrategy::PollRoute(poll_route) => {
+                poll_route.routes = endpoints
+                    .iter()
+                    .map(|item| PollBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::HeaderRoute(_) => {
+                return Err(AppError(String::from(
+                    "The HeaderRoute can not be used with the service discovery",
+                )));
+            }
+            LoadbalancerStrategy::RandomRoute(random_route) => {
+                random_route.routes = endpoints
+                    .iter()
+                    .map(|item| RandomBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::WeightRoute(weight_route) => {
+                weight_route.routes = endpoints
+                    .iter()
+                    .map(|item| WeightRouteNestedItem {
+                        base_route: item.get_base_route(),
+                        weight: item.weight.unwrap_or(1),
+                        current_weight: AtomicCounter::default(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::LeastConnection(least_connection_route) => {
+                least_connection_route.routes = endpoints
+                    .iter()
+                    .map(|item| LeastConnectionBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::PeakEwma(peak_ewma_route) => {
+                peak_ewma_route.routes = endpoints
+                    .iter()
+                    .map(|item| PeakEwmaBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::HashRoute(hash_route) => {
+                hash_route.hash_ring = OnceLock::new();
+                hash_route.routes = endpoints
+                    .iter()
+                    .map(|item| HashBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+            LoadbalancerStrategy::LocalityRoute(locality_route) => {
+                locality_route.routes = endpoints
+                    .iter()
+                    .map(|item| LocalityBaseRoute {
+                        base_route: item.get_base_route(),
+                    })
+                    .collect();
+            }
+        }
+        self.update_cluster(new_cluster)
+    }
+    pub fn get_alive_route_by_id(
+        &self,
+        base_route_id: &str,
+    ) -> Result<Option<BaseRoute>, AppError> {
+        let base_route = self
+            .get_base_routes()
+            .into_iter()
+            .find(|item| item.base_route_id == base_route_id && item.is_alive.unwrap_or(true))
+            .cloned();
+        Ok(base_route)
     }
 }
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct AnomalyDetectionStatus {
     pub consecutive_5xx: i32,
 }
-#[derive(Debug, Clone, Deserialize, Default, PartialEq, Serialize)]
+// The in flight count and the latency are shared by every clone of the BaseRoute,
+// so the proxy can report a finished request after it has released the config lock.
+#[derive(Debug, Clone, Default)]
+pub struct LoadStatus {
+    inner: Arc<LoadStatusInner>,
+}
+#[derive(Debug, Default)]
+struct LoadStatusInner {
+    in_flight: AtomicI64,
+    ewma_latency_nanos: AtomicU64,
+    last_update_nanos: AtomicU64,
+    warm_up_start_nanos: AtomicU64,
+    consecutive_failures: AtomicU64,
+}
+// The load is runtime state, two routes with the same config are equal.
+impl PartialEq for LoadStatus {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl LoadStatus {
+    pub fn in_flight(&self) -> i64 {
+        self.inner.in_flight.load(Ordering::Relaxed)
+    }
+    pub fn start_request(&self) -> InFlightGuard {
+        self.inner.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard {
+            load_status: self.clone(),
+            start_time: Instant::now(),
+        }
+    }
+    fn finish_request(&self, latency: Duration) {
+        self.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let now = now_nanos();
+        let last_update = self.inner.last_update_nanos.swap(now, Ordering::Relaxed);
+        let older = f64::from_bits(self.inner.ewma_latency_nanos.load(Ordering::Relaxed));
+        let rtt = latency.as_nanos() as f64;
+        // The peak is taken at once, lower latencies are merged slowly.
+        let current = if older == 0.0 || rtt > older {
+            rtt
+        } else {
+            let weight = decay_weight(now.saturating_sub(last_update));
+            older * weight + rtt * (1.0 - weight)
+        };
+        self.inner
+            .ewma_latency_nanos
+            .store(current.to_bits(), Ordering::Relaxed);
+    }
+    pub fn start_warm_up(&self) {
+        self.inner
+            .warm_up_start_nanos
+            .store(now_nanos().max(1), Ordering::Relaxed);
+    }
+    pub fn warm_up_elapsed(&self) -> Option<Duration> {
+        let warm_up_start = self.inner.warm_up_start_nanos.load(Ordering::Relaxed);
+        if warm_up_start == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            now_nanos().saturating_sub(warm_up_start),
+        ))
+    }
+    // Updated with the result of every upstream request, a route is down after a few failures in
+    // a row and starts to warm up again on the first success after that.
+    pub fn update_liveness(&self, is_alive: bool) {
+        if !is_alive {
+            self.inner
+                .consecutive_failures
+                .fetch_add(1, Ordering::Relaxed);
+        } else if self.inner.consecutive_failures.swap(0, Ordering::Relaxed)
+            >= SLOW_START_FAILURE_THRESHOLD
+        {
+            self.start_warm_up();
+        }
+    }
+    pub fn peak_ewma_cost(&self) -> f64 {
+        let ewma = f64::from_bits(self.inner.ewma_latency_nanos.load(Ordering::Relaxed));
+        let latency = if ewma == 0.0 {
+            (DEFAULT_PEAK_EWMA_RTT_MILLIS * 1_000_000) as f64
+        } else {
+            let last_update = self.inner.last_update_nanos.load(Ordering::Relaxed);
+            ewma * decay_weight(now_nanos().saturating_sub(last_update))
+        };
+        latency * (self.in_flight().max(0) + 1) as f64
+    }
+}
+pub struct InFlightGuard {
+    load_status: LoadStatus,
+    start_time: Instant,
+}
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.load_status.finish_request(self.start_time.elapsed());
+    }
+}
+// The counters of the balancers are shared by every clone of the cluster, so a route can be
+// picked under the read lock of the config.
+#[derive(Debug, Clone, Default)]
+pub struct AtomicCounter {
+    inner: Arc<AtomicI64>,
+}
+impl PartialEq for AtomicCounter {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl AtomicCounter {
+    // Returns the value after the addition.
+    pub fn add(&self, value: i64) -> i64 {
+        self.inner.fetch_add(value, Ordering::Relaxed) + value
+    }
+}
+fn now_nanos() -> u64 {
+    static START_TIME: OnceLock<Instant> = OnceLock::new();
+    START_TIME.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+fn decay_weight(elapsed_nanos: u64) -> f64 {
+    let decay_nanos = (DEFAULT_PEAK_EWMA_DECAY_MILLIS * 1_000_000) as f64;
+    (-(elapsed_nanos as f64) / decay_nanos).exp()
+}
+fn get_weight_factor(base_route: &BaseRoute, slow_start_config: Option<&SlowStartConfig>) -> f64 {
+    slow_start_config.map_or(1.0, |item| item.get_weight_factor(&base_route.load_status))
+}
+// Picks a random route, a warming up route is picked in proportion to its weight factor.
+fn get_route_by_weight_factor(
+    alive_cluster: Vec<&BaseRoute>,
+    slow_start_config: Option<&SlowStartConfig>,
+) -> Result<BaseRoute, AppError> {
+    if alive_cluster.is_empty() {
+        return Err(AppError(String::from(
+            "Can not find alive host in the clusters",
+        )));
+    }
+    let factors: Vec<f64> = alive_cluster
+        .iter()
+        .map(|item| get_weight_factor(item, slow_start_config))
+        .collect();
+    let total_factor: f64 = factors.iter().sum();
+    let mut rng = thread_rng();
+    if total_factor <= 0.0 {
+        return Ok(alive_cluster[rng.gen_range(0..alive_cluster.len())].clone());
+    }
+    let mut offset = rng.gen_range(0.0..total_factor);
+    for (base_route, factor) in alive_cluster.iter().zip(factors) {
+        if offset < factor {
+            return Ok((*base_route).clone());
+        }
+        offset -= factor;
+    }
+    Ok(alive_cluster[alive_cluster.len() - 1].clone())
+}
+// Power of two choices: take two random alive routes and keep the one with the lower load.
+fn get_route_by_two_choices(
+    base_routes: Vec<&BaseRoute>,
+    load: impl Fn(&BaseRoute) -> f64,
+) -> Result<BaseRoute, AppError> {
+    let alive_cluster: Vec<&BaseRoute> = base_routes
+        .into_iter()
+        .filter(|item| item.is_alive.unwrap_or(true))
+        .collect();
+    if alive_cluster.is_empty() {
+        return Err(AppError(String::from(
+            "Can not find alive host in the clusters",
+        )));
+    }
+    if alive_cluster.len() == 1 {
+        return Ok(alive_cluster[0].clone());
+    }
+    let mut rng = thread_rng();
+    let first = rng.gen_range(0..alive_cluster.len());
+    let mut second = rng.gen_range(0..alive_cluster.len() - 1);
+    if second >= first {
+        second += 1;
+    }
+    let (first_route, second_route) = (alive_cluster[first], alive_cluster[second]);
+    if load(second_route) < load(first_route) {
+        Ok(second_route.clone())
+    } else {
+        Ok(first_route.clone())
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct BaseRoute {
     pub endpoint: String,
     pub try_file: Option<String>,
-    #[serde(default = "default_base_route_id")]
     pub base_route_id: String,
     #[serde(skip_deserializing)]
     pub is_alive: Option<bool>,
     #[serde(skip_serializing, skip_deserializing)]
     pub anomaly_detection_status: AnomalyDetectionStatus,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub load_status: LoadStatus,
+    pub locality: Option<Locality>,
+    #[serde(default)]
+    pub priority: u32,
 }
-fn default_base_route_id() -> String {
-    "aa".to_string()
+// The id defaults to the hash of the endpoint, so every gateway and every restart gives the same
+// id to the endpoint and the affinity cookie keeps working.
+impl<'de> Deserialize<'de> for BaseRoute {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct BaseRouteConfig {
+            endpoint: String,
+            try_file: Option<String>,
+            base_route_id: Option<String>,
+            locality: Option<Locality>,
+            #[serde(default)]
+            priority: u32,
+        }
+        let config = BaseRouteConfig::deserialize(deserializer)?;
+        Ok(BaseRoute {
+            base_route_id: config
+                .base_route_id
+                .unwrap_or_else(|| get_base_route_id(&config.endpoint)),
+            endpoint: config.endpoint,
+            try_file: config.try_file,
+            locality: config.locality,
+            priority: config.priority,
+            ..Default::default()
+        })
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredEndpoint {
+    pub endpoint: String,
+    pub weight: Option<u64>,
+    pub locality: Option<Locality>,
+    #[serde(default)]
+    pub priority: u32,
+}
+impl DiscoveredEndpoint {
+    fn get_base_route(&self) -> BaseRoute {
+        BaseRoute {
+            endpoint: self.endpoint.clone(),
+            base_route_id: get_base_route_id(&self.endpoint),
+            locality: self.locality.clone(),
+            priority: self.priority,
+            ..Default::default()
+        }
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Locality {
+    pub zone: Option<String>,
+    pub region: Option<String>,
+}
+fn get_base_route_id(endpoint: &str) -> String {
+    format!("{:016x}", get_hash(endpoint))
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct WeightRouteNestedItem {
     pub base_route: BaseRoute,
     pub weight: u64,
+    #[serde(skip)]
+    pub current_weight: AtomicCounter,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -119,7 +586,7 @@ impl HeaderRoute {
         Ok(vecs)
     }
 
-    async fn get_route(&mut self, headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
+    fn get_route(&self, headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
         let mut alive_cluster: Vec<HeaderRouteNestedItem> = vec![];
         for item in self.routes.clone() {
             let is_alve_result = item.base_route.is_alive;
@@ -196,19 +663,18 @@ impl RandomRoute {
         Ok(vecs)
     }
 
-    async fn get_route(&mut self, _headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
-        let mut alive_cluster: Vec<BaseRoute> = vec![];
-        for item in self.routes.clone() {
-            let is_alve_result = item.base_route.is_alive;
-            let is_alive = is_alve_result.unwrap_or(true);
-            if is_alive {
-                alive_cluster.push(item.base_route.clone());
-            }
-        }
-        let mut rng = thread_rng();
-        let index = rng.gen_range(0..alive_cluster.len());
-        let dst = alive_cluster[index].clone();
-        Ok(dst)
+    fn get_route(
+        &self,
+        _headers: HeaderMap<HeaderValue>,
+        slow_start_config: Option<&SlowStartConfig>,
+    ) -> Result<BaseRoute, AppError> {
+        let alive_cluster: Vec<&BaseRoute> = self
+            .routes
+            .iter()
+            .map(|item| &item.base_route)
+            .filter(|item| item.is_alive.unwrap_or(true))
+            .collect();
+        get_route_by_weight_factor(alive_cluster, slow_start_config)
     }
 }
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
@@ -219,7 +685,7 @@ pub struct PollBaseRoute {
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct PollRoute {
     #[serde(skip)]
-    pub current_index: i64,
+    pub current_index: AtomicCounter,
     pub routes: Vec<PollBaseRoute>,
 }
 
@@ -233,7 +699,7 @@ impl PollRoute {
         Ok(vecs)
     }
 
-    async fn get_route(&mut self, _headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
+    fn get_route(&self, _headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
         let mut alive_cluster: Vec<PollBaseRoute> = vec![];
         for item in self.routes.clone() {
             let is_alve_result = item.base_route.is_alive;
@@ -247,10 +713,10 @@ impl PollRoute {
                 "Can not find alive host in the clusters",
             )));
         }
-        let older = self.current_index;
         let len = alive_cluster.len();
-        let current_index = (older + 1) % len as i64;
-        self.current_index = current_index;
+        let counter = self.current_index.add(1);
+        let older = (counter - 1).rem_euclid(len as i64);
+        let current_index = counter.rem_euclid(len as i64);
         let dst = alive_cluster[current_index as usize].clone();
         let level_filter = tracing_subscriber::filter::LevelFilter::current();
 
@@ -266,8 +732,6 @@ impl PollRoute {
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct WeightRoute {
     pub routes: Vec<WeightRouteNestedItem>,
-    pub index: u64,
-    pub offset: u64,
 }
 
 impl WeightRoute {
@@ -279,32 +743,350 @@ impl WeightRoute {
             .collect::<Vec<&mut BaseRoute>>();
         Ok(vecs)
     }
-
-    async fn get_route(&mut self, _headers: HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
-        let cluster_read_lock2 = self.routes.clone();
-        loop {
-            let currnet_index = self.index;
-            let offset = self.offset;
-            let current_weight = cluster_read_lock2
-                .get(currnet_index as usize)
-                .ok_or(AppError(String::from("")))?;
-            let is_alive = current_weight.base_route.is_alive.unwrap_or(true);
-            if current_weight.weight > offset && is_alive {
-                self.offset += 1;
-                return Ok(current_weight.base_route.clone());
-            }
-            if current_weight.weight <= offset {
-                self.offset = 0;
-                self.index = (self.index + 1) % cluster_read_lock2.len() as u64;
+    // Smooth weighted round robin, the same as the upstream module of nginx.
+    fn get_route(
+        &self,
+        _headers: HeaderMap<HeaderValue>,
+        slow_start_config: Option<&SlowStartConfig>,
+    ) -> Result<BaseRoute, AppError> {
+        let mut total_weight = 0;
+        let mut best_index = None;
+        for (index, item) in self.routes.iter().enumerate() {
+            if item.weight == 0 || !item.base_route.is_alive.unwrap_or(true) {
                 continue;
             }
-            if !is_alive {
-                self.offset = 0;
-                self.index = (self.index + 1) % cluster_read_lock2.len() as u64;
-                continue;
+            // The weights are scaled up, so a part of a small weight is not rounded away.
+            let factor = get_weight_factor(&item.base_route, slow_start_config);
+            let weight = ((item.weight * SLOW_START_WEIGHT_SCALE) as f64 * factor)
+                .round()
+                .max(1.0) as i64;
+            let current_weight = item.current_weight.add(weight);
+            total_weight += weight;
+            match best_index {
+                Some((_, best_weight)) if best_weight >= current_weight => {}
+                _ => best_index = Some((index, current_weight)),
             }
         }
+        let (index, _) = best_index.ok_or(AppError(String::from(
+            "Can not find alive host in the clusters",
+        )))?;
+        let best = &self.routes[index];
+        best.current_weight.add(-total_weight);
+        Ok(best.base_route.clone())
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LeastConnectionBaseRoute {
+    pub base_route: BaseRoute,
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LeastConnectionRoute {
+    pub routes: Vec<LeastConnectionBaseRoute>,
+}
 
-        // Err(AppError(String::from("WeightRoute get route error")))
+impl LeastConnectionRoute {
+    fn get_all_route(&mut self) -> Result<Vec<&mut BaseRoute>, AppError> {
+        let vecs = self
+            .routes
+            .iter_mut()
+            .map(|item| &mut item.base_route)
+            .collect::<Vec<&mut BaseRoute>>();
+        Ok(vecs)
+    }
+
+    fn get_route(
+        &self,
+        _headers: HeaderMap<HeaderValue>,
+        slow_start_config: Option<&SlowStartConfig>,
+    ) -> Result<BaseRoute, AppError> {
+        let base_routes = self.routes.iter().map(|item| &item.base_route).collect();
+        // The request being picked is counted, so a warming up route without requests still
+        // looks more loaded.
+        get_route_by_two_choices(base_routes, |base_route| {
+            (base_route.load_status.in_flight().max(0) + 1) as f64
+                / get_weight_factor(base_route, slow_start_config)
+        })
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeakEwmaBaseRoute {
+    pub base_route: BaseRoute,
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeakEwmaRoute {
+    pub routes: Vec<PeakEwmaBaseRoute>,
+}
+
+impl PeakEwmaRoute {
+    fn get_all_route(&mut self) -> Result<Vec<&mut BaseRoute>, AppError> {
+        let vecs = self
+            .routes
+            .iter_mut()
+            .map(|item| &mut item.base_route)
+            .collect::<Vec<&mut BaseRoute>>();
+        Ok(vecs)
+    }
+
+    fn get_route(
+        &self,
+        _headers: HeaderMap<HeaderValue>,
+        slow_start_config: Option<&SlowStartConfig>,
+    ) -> Result<BaseRoute, AppError> {
+        let base_routes = self.routes.iter().map(|item| &item.base_route).collect();
+        get_route_by_two_choices(base_routes, |base_route| {
+            base_route.load_status.peak_ewma_cost()
+                / get_weight_factor(base_route, slow_start_config)
+        })
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeaderHashKey {
+    pub key: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CookieHashKey {
+    pub key: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryHashKey {
+    pub key: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type")]
+pub enum HashKeyLocation {
+    #[default]
+    ClientIp,
+    Header(HeaderHashKey),
+    Cookie(CookieHashKey),
+    Query(QueryHashKey),
+}
+impl HashKeyLocation {
+    fn get_hash_key(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        remote_ip: String,
+        uri: &Uri,
+    ) -> Option<String> {
+        match self {
+            HashKeyLocation::ClientIp => Some(remote_ip),
+            HashKeyLocation::Header(header_hash_key) => headers
+                .get(header_hash_key.key.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            HashKeyLocation::Cookie(cookie_hash_key) => {
+                get_cookie_value(headers, &cookie_hash_key.key)
+            }
+            HashKeyLocation::Query(query_hash_key) => get_query_value(uri, &query_hash_key.key),
+        }
+    }
+}
+pub fn get_cookie_value(headers: &HeaderMap<HeaderValue>, name: &str) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|item| item.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+pub fn get_query_value(uri: &Uri, name: &str) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|item| item.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+// The hash must be the same on every gateway and every build, the ids and the ring depend on it. It is
+// the FNV-1a with the finalizer of the murmur3, so the ring nodes of the similar keys are spread out.
+fn get_hash(value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for item in value.as_bytes() {
+        hash ^= *item as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+// The ring is built from every configured route, the dead routes are skipped at lookup time,
+// so only the keys of the changed routes are remapped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashRing {
+    endpoints: Vec<String>,
+    nodes: Vec<(u64, usize)>,
+}
+impl HashRing {
+    fn new(endpoints: Vec<String>) -> Self {
+        let mut nodes = vec![];
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            for replica in 0..DEFAULT_HASH_RING_VIRTUAL_NODES {
+                nodes.push((get_hash(&format!("{}-{}", endpoint, replica)), index));
+            }
+        }
+        nodes.sort_unstable();
+        HashRing { endpoints, nodes }
+    }
+    fn get_index(&self, hash_key: &str, is_alive: impl Fn(usize) -> bool) -> Option<usize> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let hash = get_hash(hash_key);
+        let start = self
+            .nodes
+            .partition_point(|(node_hash, _)| *node_hash < hash);
+        (0..self.nodes.len())
+            .map(|offset| self.nodes[(start + offset) % self.nodes.len()].1)
+            .find(|index| is_alive(*index))
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HashBaseRoute {
+    pub base_route: BaseRoute,
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HashRoute {
+    pub hash_key: HashKeyLocation,
+    pub routes: Vec<HashBaseRoute>,
+    // Built on the first lookup, a new cluster starts with an empty ring.
+    #[serde(skip)]
+    pub hash_ring: OnceLock<HashRing>,
+}
+
+impl HashRoute {
+    fn get_all_route(&mut self) -> Result<Vec<&mut BaseRoute>, AppError> {
+        let vecs = self
+            .routes
+            .iter_mut()
+            .map(|item| &mut item.base_route)
+            .collect::<Vec<&mut BaseRoute>>();
+        Ok(vecs)
+    }
+
+    fn get_route(
+        &self,
+        headers: HeaderMap<HeaderValue>,
+        remote_ip: String,
+        uri: Uri,
+    ) -> Result<BaseRoute, AppError> {
+        let hash_ring = self.hash_ring.get_or_init(|| {
+            HashRing::new(
+                self.routes
+                    .iter()
+                    .map(|item| item.base_route.endpoint.clone())
+                    .collect(),
+            )
+        });
+        let alive_cluster: Vec<&BaseRoute> = self
+            .routes
+            .iter()
+            .map(|item| &item.base_route)
+            .filter(|item| item.is_alive.unwrap_or(true))
+            .collect();
+        if alive_cluster.is_empty() {
+            return Err(AppError(String::from(
+                "Can not find alive host in the clusters",
+            )));
+        }
+        let hash_key_option = self.hash_key.get_hash_key(&headers, remote_ip, &uri);
+        let Some(hash_key) = hash_key_option else {
+            debug!("Can not find the hash key,HashRoute has selected a random route!");
+            let index = thread_rng().gen_range(0..alive_cluster.len());
+            return Ok(alive_cluster[index].clone());
+        };
+        let index = hash_ring
+            .get_index(&hash_key, |index| {
+                self.routes[index].base_route.is_alive.unwrap_or(true)
+            })
+            .ok_or(AppError(String::from(
+                "Can not find alive host in the clusters",
+            )))?;
+        Ok(self.routes[index].base_route.clone())
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LocalityBaseRoute {
+    pub base_route: BaseRoute,
+}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LocalityRoute {
+    pub routes: Vec<LocalityBaseRoute>,
+}
+
+impl LocalityRoute {
+    fn get_all_route(&mut self) -> Result<Vec<&mut BaseRoute>, AppError> {
+        let vecs = self
+            .routes
+            .iter_mut()
+            .map(|item| &mut item.base_route)
+            .collect::<Vec<&mut BaseRoute>>();
+        Ok(vecs)
+    }
+    // Use the first priority which still has enough alive routes, and inside of it
+    // prefer the routes in the zone of the gateway, then the ones in its region.
+    fn get_route(
+        &self,
+        _headers: HeaderMap<HeaderValue>,
+        local_locality: Option<Locality>,
+        min_liveness_count: i32,
+        slow_start_config: Option<&SlowStartConfig>,
+    ) -> Result<BaseRoute, AppError> {
+        let mut priorities: Vec<u32> = self
+            .routes
+            .iter()
+            .map(|item| item.base_route.priority)
+            .collect();
+        priorities.sort_unstable();
+        priorities.dedup();
+        let mut alive_cluster: Vec<&BaseRoute> = vec![];
+        for priority in priorities {
+            let priority_cluster: Vec<&BaseRoute> = self
+                .routes
+                .iter()
+                .map(|item| &item.base_route)
+                .filter(|item| item.priority == priority && item.is_alive.unwrap_or(true))
+                .collect();
+            if priority_cluster.len() >= min_liveness_count.max(1) as usize {
+                alive_cluster = priority_cluster;
+                break;
+            }
+            // When no priority has enough alive routes, keep the one with the most.
+            if priority_cluster.len() > alive_cluster.len() {
+                alive_cluster = priority_cluster;
+            }
+        }
+        if alive_cluster.is_empty() {
+            return Err(AppError(String::from(
+                "Can not find alive host in the clusters",
+            )));
+        }
+        if let Some(local_locality) = local_locality {
+            let same_zone: Vec<&BaseRoute> = alive_cluster
+                .iter()
+                .filter(|item| {
+                    local_locality.zone.is_some()
+                        && item.locality.as_ref().and_then(|l| l.zone.as_ref())
+                            == local_locality.zone.as_ref()
+                })
+                .copied()
+                .collect();
+            let same_region: Vec<&BaseRoute> = alive_cluster
+                .iter()
+                .filter(|item| {
+                    local_locality.region.is_some()
+                        && item.locality.as_ref().and_then(|l| l.region.as_ref())
+                            == local_locality.region.as_ref()
+                })
+                .copied()
+                .collect();
+            if !same_zone.is_empty() {
+                alive_cluster = same_zone;
+            } else if !same_region.is_empty() {
+                alive_cluster = same_region;
+            }
+        }
+        get_route_by_weight_factor(alive_cluster, slow_start_config)
     }
 }
@@ -0,0 +1,119 @@
+use crate::constants::common_constants::DEFAULT_REDIRECT_STATUS;
+use crate::ensure;
+use crate::vojo::app_error::AppError;
+use http::header::HOST;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use http::Uri;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// The routes with an action never send the requests to the route cluster.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RouteAction {
+    Redirect(RedirectAction),
+    DirectResponse(DirectResponseAction),
+}
+impl RouteAction {
+    pub fn get_status(&self) -> Result<StatusCode, AppError> {
+        match self {
+            RouteAction::Redirect(redirect) => {
+                let status =
+                    StatusCode::from_u16(redirect.status).map_err(|e| AppError(e.to_string()))?;
+                ensure!(
+                    status.is_redirection(),
+                    format!("The status {} is not a redirect", redirect.status)
+                );
+                Ok(status)
+            }
+            RouteAction::DirectResponse(direct_response) => {
+                StatusCode::from_u16(direct_response.status).map_err(|e| AppError(e.to_string()))
+            }
+        }
+    }
+}
+fn default_redirect_status() -> u16 {
+    DEFAULT_REDIRECT_STATUS
+}
+// The parts which are not set are taken from the request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectAction {
+    #[serde(default)]
+    pub https_redirect: bool,
+    pub host_redirect: Option<String>,
+    pub port_redirect: Option<u16>,
+    // Replaces the whole path.
+    pub path_redirect: Option<String>,
+    // Replaces the prefix of the matcher.
+    pub prefix_redirect: Option<String>,
+    #[serde(default)]
+    pub strip_query: bool,
+    // One of 301, 302, 303, 307 and 308.
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+impl RedirectAction {
+    pub fn get_location(
+        &self,
+        uri: &Uri,
+        headers: &HeaderMap<HeaderValue>,
+        scheme: &str,
+        matched_prefix: Option<&str>,
+    ) -> Result<String, AppError> {
+        let request_host = headers
+            .get(HOST)
+            .and_then(|item| item.to_str().ok())
+            .or(uri.authority().map(|item| item.as_str()))
+            .ok_or(AppError(String::from("The request has no host")))?;
+        let (request_host_name, request_port) = split_host_port(request_host);
+        let host_name = self.host_redirect.as_deref().unwrap_or(request_host_name);
+        // The default port of the new scheme is used for the https redirect.
+        let port = match (self.port_redirect, self.https_redirect) {
+            (Some(port), _) => Some(port.to_string()),
+            (None, true) => None,
+            (None, false) => request_port.map(|item| item.to_string()),
+        };
+        let scheme = if self.https_redirect { "https" } else { scheme };
+        let path = match (&self.path_redirect, &self.prefix_redirect, matched_prefix) {
+            (Some(path_redirect), _, _) => path_redirect.clone(),
+            (None, Some(prefix_redirect), Some(prefix)) => {
+                let rest_path = uri.path().strip_prefix(prefix).unwrap_or(uri.path());
+                format!("{}{}", prefix_redirect, rest_path)
+            }
+            _ => uri.path().to_string(),
+        };
+        let mut location = format!("{}://{}", scheme, host_name);
+        if let Some(port) = port {
+            location.push_str(&format!(":{}", port));
+        }
+        location.push_str(&path);
+        if let (false, Some(query)) = (self.strip_query, uri.query()) {
+            location.push_str(&format!("?{}", query));
+        }
+        Ok(location)
+    }
+}
+// The ipv6 host is enclosed in the square brackets.
+fn split_host_port(host: &str) -> (&str, Option<&str>) {
+    match host.rsplit_once(':') {
+        Some((host_name, port))
+            if !port.is_empty()
+                && port.bytes().all(|item| item.is_ascii_digit())
+                && (!host_name.contains(':') || host_name.ends_with(']')) =>
+        {
+            (host_name, Some(port))
+        }
+        _ => (host, None),
+    }
+}
+// The body is inline or read from the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectResponseAction {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub body: Option<String>,
+    pub body_file: Option<String>,
+}
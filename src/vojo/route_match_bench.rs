@@ -0,0 +1,87 @@
+extern crate test;
+
+use crate::vojo::app_config::ServiceConfig;
+use http::header::HOST;
+use http::{HeaderMap, HeaderValue, Method, Uri};
+use serde_json::json;
+use test::Bencher;
+
+const ROUTE_COUNT: usize = 100;
+
+// The routes are looked up the same way as the route service does, the last route is matched.
+fn get_service_config(matchers: impl Fn(usize) -> serde_json::Value) -> ServiceConfig {
+    let routes: Vec<serde_json::Value> = (0..ROUTE_COUNT)
+        .map(|index| {
+            let mut route = json!({
+                "route_id": format!("route{}", index),
+                "route_cluster": {
+                    "type": "PollRoute",
+                    "routes": [{"base_route": {"endpoint": "http://127.0.0.1:9001"}}]
+                }
+            });
+            route
+                .as_object_mut()
+                .unwrap()
+                .extend(matchers(index).as_object().unwrap().clone());
+            route
+        })
+        .collect();
+    serde_json::from_value(json!({"server_type": "Http", "routes": routes})).unwrap()
+}
+fn match_route(
+    service_config: &ServiceConfig,
+    uri: &Uri,
+    headers: &HeaderMap<HeaderValue>,
+) -> Option<String> {
+    for index in service_config.get_route_candidates(uri.path()).unwrap() {
+        let route = &service_config.routes[index];
+        if let Some(final_path) = route.is_matched(&Method::GET, uri, headers).unwrap() {
+            return Some(final_path);
+        }
+    }
+    None
+}
+fn bench_match(bencher: &mut Bencher, service_config: ServiceConfig, uri: &str, host: &str) {
+    let uri = uri.parse::<Uri>().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(HOST, HeaderValue::from_str(host).unwrap());
+    assert!(match_route(&service_config, &uri, &headers).is_some());
+    bencher.iter(|| match_route(test::black_box(&service_config), &uri, &headers));
+}
+#[bench]
+fn prefix_route_match(bencher: &mut Bencher) {
+    let service_config = get_service_config(
+        |index| json!({"matcher": {"prefix": format!("/api/service{}/", index), "prefix_rewrite": "/"}}),
+    );
+    bench_match(
+        bencher,
+        service_config,
+        "/api/service99/users/1",
+        "example.com",
+    );
+}
+#[bench]
+fn regex_route_match(bencher: &mut Bencher) {
+    let service_config = get_service_config(|index| {
+        json!({"matcher": {
+            "regex_path": format!("^/api/service{}/users/(?P<id>[0-9]+)$", index),
+            "path_rewrite": "/users/${id}"
+        }})
+    });
+    bench_match(
+        bencher,
+        service_config,
+        "/api/service99/users/1",
+        "example.com",
+    );
+}
+#[bench]
+fn host_regex_route_match(bencher: &mut Bencher) {
+    let service_config = get_service_config(|index| {
+        json!({
+            "host_name": format!("^service{}\\.example\\.com$", index),
+            "matcher": {"prefix": "/", "prefix_rewrite": "/"}
+        })
+    });
+    bench_match(bencher, service_config, "/users/1", "service99.example.com");
+}
@@ -0,0 +1,319 @@
+use crate::proxy::static_file::get_static_root;
+use crate::vojo::app_config::get_cached_regex;
+use crate::vojo::app_config::Matcher;
+use crate::vojo::app_config::Route;
+use crate::vojo::app_error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathMatcher<'a> {
+    Exact(&'a str),
+    Regex(&'a str),
+    Prefix(&'a str),
+    None,
+}
+impl<'a> PathMatcher<'a> {
+    fn new(route: &'a Route) -> Self {
+        match &route.matcher {
+            Some(Matcher {
+                path: Some(path), ..
+            }) => PathMatcher::Exact(path),
+            Some(Matcher {
+                regex_path: Some(regex_path),
+                ..
+            }) => PathMatcher::Regex(regex_path),
+            Some(matcher) => PathMatcher::Prefix(&matcher.prefix),
+            None => PathMatcher::None,
+        }
+    }
+    // Exact paths are checked first, then the regex paths, then the longest prefix.
+    fn get_rank(&self) -> (usize, std::cmp::Reverse<usize>) {
+        match self {
+            PathMatcher::Exact(path) => (0, std::cmp::Reverse(path.len())),
+            PathMatcher::Regex(_) => (1, std::cmp::Reverse(0)),
+            PathMatcher::Prefix(prefix) => (2, std::cmp::Reverse(prefix.len())),
+            PathMatcher::None => (3, std::cmp::Reverse(0)),
+        }
+    }
+    fn is_catch_all(&self) -> bool {
+        matches!(self, PathMatcher::Prefix(prefix) if prefix.is_empty() || *prefix == "/")
+    }
+    // Every path matched by the other matcher is also matched by this one.
+    fn covers(&self, other: &PathMatcher) -> bool {
+        match (self, other) {
+            (PathMatcher::Prefix(_), _) if self.is_catch_all() => true,
+            (PathMatcher::Prefix(prefix), PathMatcher::Prefix(path))
+            | (PathMatcher::Prefix(prefix), PathMatcher::Exact(path)) => path.starts_with(prefix),
+            (PathMatcher::Exact(path), PathMatcher::Exact(other_path)) => path == other_path,
+            (PathMatcher::Regex(regex), PathMatcher::Regex(other_regex)) => regex == other_regex,
+            _ => false,
+        }
+    }
+    fn overlaps(&self, other: &PathMatcher) -> bool {
+        self.covers(other) || other.covers(self)
+    }
+}
+#[derive(Debug, Clone, Default)]
+struct RadixNode {
+    label: Vec<u8>,
+    children: Vec<RadixNode>,
+    values: Vec<usize>,
+}
+impl RadixNode {
+    fn insert(&mut self, key: &[u8], value: usize) {
+        if key.is_empty() {
+            self.values.push(value);
+            return;
+        }
+        for child in self.children.iter_mut() {
+            let common_len = child
+                .label
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common_len == 0 {
+                continue;
+            }
+            if common_len < child.label.len() {
+                let split_node = RadixNode {
+                    label: child.label.split_off(common_len),
+                    children: std::mem::take(&mut child.children),
+                    values: std::mem::take(&mut child.values),
+                };
+                child.children.push(split_node);
+            }
+            child.insert(&key[common_len..], value);
+            return;
+        }
+        self.children.push(RadixNode {
+            label: key.to_vec(),
+            children: vec![],
+            values: vec![value],
+        });
+    }
+    // Collects the values of all the prefixes of the key.
+    fn collect(&self, key: &[u8], result: &mut Vec<usize>) {
+        result.extend_from_slice(&self.values);
+        if let Some(child) = self
+            .children
+            .iter()
+            .find(|item| key.starts_with(&item.label))
+        {
+            child.collect(&key[child.label.len()..], result);
+        }
+    }
+}
+// The routes of a service compiled once per change of the routes, the lookup returns the indexes of
+// the routes which may match the path in the order they should be checked.
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    ranks: Vec<usize>,
+    exact_routes: HashMap<String, Vec<usize>>,
+    prefix_routes: RadixNode,
+    other_routes: Vec<usize>,
+    // The canonical roots of the static file endpoints.
+    static_roots: HashMap<String, PathBuf>,
+}
+impl RouteTable {
+    pub fn new(routes: &[Route]) -> Result<Self, AppError> {
+        let mut route_table = RouteTable {
+            ranks: vec![0; routes.len()],
+            ..Default::default()
+        };
+        for (rank, index) in get_ordered_indexes(routes).into_iter().enumerate() {
+            route_table.ranks[index] = rank;
+        }
+        for (index, route) in routes.iter().enumerate() {
+            for pattern in get_regex_patterns(route) {
+                get_cached_regex(pattern)?;
+            }
+            if let Some(cors) = &route.cors {
+                cors.check()?;
+            }
+            if let Some(action) = &route.action {
+                action.get_status()?;
+            }
+            route.check_slow_start()?;
+            route.check_header_operations()?;
+            for base_route in route.get_base_routes() {
+                if base_route.try_file.is_some() {
+                    let root = get_static_root(&base_route.endpoint)?;
+                    route_table
+                        .static_roots
+                        .insert(base_route.endpoint.clone(), root);
+                }
+            }
+            match PathMatcher::new(route) {
+                PathMatcher::Exact(path) => route_table
+                    .exact_routes
+                    .entry(path.to_string())
+                    .or_default()
+                    .push(index),
+                PathMatcher::Prefix(prefix) => {
+                    route_table.prefix_routes.insert(prefix.as_bytes(), index)
+                }
+                PathMatcher::Regex(_) | PathMatcher::None => route_table.other_routes.push(index),
+            }
+        }
+        Ok(route_table)
+    }
+    pub fn get_candidates(&self, path: &str) -> Vec<usize> {
+        let mut candidates = self.other_routes.clone();
+        if let Some(exact_routes) = self.exact_routes.get(path) {
+            candidates.extend_from_slice(exact_routes);
+        }
+        self.prefix_routes.collect(path.as_bytes(), &mut candidates);
+        candidates.sort_unstable_by_key(|index| self.ranks[*index]);
+        candidates
+    }
+    pub fn get_static_root(&self, endpoint: &str) -> Option<PathBuf> {
+        self.static_roots.get(endpoint).cloned()
+    }
+}
+// The higher priority is checked first, the order of the config breaks the ties.
+fn get_ordered_indexes(routes: &[Route]) -> Vec<usize> {
+    let mut indexes: Vec<usize> = (0..routes.len()).collect();
+    indexes.sort_by_key(|index| {
+        let route = &routes[*index];
+        (
+            std::cmp::Reverse(route.priority),
+            PathMatcher::new(route).get_rank(),
+        )
+    });
+    indexes
+}
+fn get_regex_patterns(route: &Route) -> Vec<&str> {
+    let mut patterns: Vec<&str> = route.host_name.iter().map(|item| item.as_str()).collect();
+    if let Some(matcher) = &route.matcher {
+        patterns.extend(matcher.regex_path.as_deref());
+        patterns.extend(
+            matcher
+                .regex_rewrite
+                .as_ref()
+                .map(|item| item.pattern.as_str()),
+        );
+        patterns.extend(
+            matcher
+                .headers
+                .iter()
+                .flatten()
+                .filter_map(|item| item.regex.as_deref()),
+        );
+        patterns.extend(
+            matcher
+                .query_params
+                .iter()
+                .flatten()
+                .filter_map(|item| item.regex.as_deref()),
+        );
+    }
+    patterns
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RouteConflictType {
+    // The lower route could never be matched.
+    Shadowed,
+    // Some requests of the lower route are taken by the higher route.
+    Overlapping,
+}
+// The higher route is checked before the lower route.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteConflict {
+    pub conflict_type: RouteConflictType,
+    pub higher_route_id: String,
+    pub lower_route_id: String,
+}
+// Every request matched by the conditions of the other route is also matched by the conditions
+// of this route, the path is not considered.
+fn conditions_cover(route: &Route, other: &Route) -> bool {
+    if route.host_name.is_some() && route.host_name != other.host_name {
+        return false;
+    }
+    let (Some(matcher), Some(other_matcher)) = (&route.matcher, &other.matcher) else {
+        return route.matcher.is_none();
+    };
+    let methods_cover = match (&matcher.methods, &other_matcher.methods) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(methods), Some(other_methods)) => other_methods.iter().all(|item| {
+            methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(item))
+        }),
+    };
+    let headers_cover = matcher.headers.iter().flatten().all(|item| {
+        other_matcher
+            .headers
+            .iter()
+            .flatten()
+            .any(|other_item| item == other_item)
+    });
+    let query_params_cover = matcher.query_params.iter().flatten().all(|item| {
+        other_matcher
+            .query_params
+            .iter()
+            .flatten()
+            .any(|other_item| item == other_item)
+    });
+    methods_cover && headers_cover && query_params_cover
+}
+fn methods_are_disjoint(route: &Route, other: &Route) -> bool {
+    let (Some(methods), Some(other_methods)) = (
+        route
+            .matcher
+            .as_ref()
+            .and_then(|item| item.methods.as_ref()),
+        other
+            .matcher
+            .as_ref()
+            .and_then(|item| item.methods.as_ref()),
+    ) else {
+        return false;
+    };
+    !methods.iter().any(|method| {
+        other_methods
+            .iter()
+            .any(|item| item.eq_ignore_ascii_case(method))
+    })
+}
+// Reports the routes which shadow or overlap the given route, the regex paths are only compared
+// with the same regex and the catch-all prefixes.
+pub fn get_route_conflicts(routes: &[Route], route_id: &str) -> Vec<RouteConflict> {
+    let ordered_indexes = get_ordered_indexes(routes);
+    let Some(position) = ordered_indexes
+        .iter()
+        .position(|index| routes[*index].route_id == route_id)
+    else {
+        return vec![];
+    };
+    let route = &routes[ordered_indexes[position]];
+    let mut conflicts = vec![];
+    for (other_position, other_index) in ordered_indexes.iter().enumerate() {
+        let other = &routes[*other_index];
+        if other_position == position || methods_are_disjoint(route, other) {
+            continue;
+        }
+        let (higher, lower) = if other_position < position {
+            (other, route)
+        } else {
+            (route, other)
+        };
+        let (higher_path, lower_path) = (PathMatcher::new(higher), PathMatcher::new(lower));
+        let conflict_type = if higher_path.covers(&lower_path) && conditions_cover(higher, lower) {
+            RouteConflictType::Shadowed
+        } else if higher_path.overlaps(&lower_path) {
+            RouteConflictType::Overlapping
+        } else {
+            continue;
+        };
+        conflicts.push(RouteConflict {
+            conflict_type,
+            higher_route_id: higher.route_id.clone(),
+            lower_route_id: lower.route_id.clone(),
+        });
+    }
+    conflicts
+}
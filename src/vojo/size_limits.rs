@@ -0,0 +1,110 @@
+use http::header::CONTENT_LENGTH;
+use http::request::Parts;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// The limits which are not set are unlimited, the decoder never accepts more than 96 headers. The
+// header limits of the listener are also checked while the head is read.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SizeLimits {
+    // The request line and the headers, with the separators and the line endings.
+    pub max_header_bytes: Option<usize>,
+    pub max_header_count: Option<usize>,
+    pub max_uri_length: Option<usize>,
+    pub max_request_body_size: Option<usize>,
+    pub max_response_body_size: Option<usize>,
+}
+impl SizeLimits {
+    // The limits of the route replace the limits of the listener one by one.
+    pub fn merge(route: Option<&SizeLimits>, service: Option<&SizeLimits>) -> SizeLimits {
+        let route = route.cloned().unwrap_or_default();
+        let service = service.cloned().unwrap_or_default();
+        SizeLimits {
+            max_header_bytes: route.max_header_bytes.or(service.max_header_bytes),
+            max_header_count: route.max_header_count.or(service.max_header_count),
+            max_uri_length: route.max_uri_length.or(service.max_uri_length),
+            max_request_body_size: route
+                .max_request_body_size
+                .or(service.max_request_body_size),
+            max_response_body_size: route
+                .max_response_body_size
+                .or(service.max_response_body_size),
+        }
+    }
+    // The declared length of the body is checked with the head, the chunked body is checked
+    // when it is read.
+    pub fn check_request_head(&self, parts: &Parts) -> Option<SizeLimitViolation> {
+        let uri_length = parts
+            .uri
+            .path_and_query()
+            .map(|item| item.as_str().len())
+            .unwrap_or_default();
+        if self.max_uri_length.is_some_and(|max| uri_length > max) {
+            return Some(SizeLimitViolation::UriTooLong);
+        }
+        if self
+            .max_header_count
+            .is_some_and(|max| parts.headers.len() > max)
+        {
+            return Some(SizeLimitViolation::TooManyHeaders);
+        }
+        // The request line is e.g. "GET /path HTTP/1.1\r\n".
+        let request_line_bytes = parts.method.as_str().len() + uri_length + 12;
+        let header_bytes: usize = request_line_bytes
+            + parts
+                .headers
+                .iter()
+                .map(|(key, value)| key.as_str().len() + value.len() + 4)
+                .sum::<usize>();
+        if self.max_header_bytes.is_some_and(|max| header_bytes > max) {
+            return Some(SizeLimitViolation::HeaderTooLarge);
+        }
+        let content_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|item| item.to_str().ok())
+            .and_then(|item| item.parse::<usize>().ok());
+        if let (Some(max), Some(content_length)) = (self.max_request_body_size, content_length) {
+            if content_length > max {
+                return Some(SizeLimitViolation::RequestBodyTooLarge);
+            }
+        }
+        None
+    }
+}
+// Attached to the extensions of the rejected response, the access log prints it as the reason.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeLimitViolation {
+    HeaderTooLarge,
+    TooManyHeaders,
+    UriTooLong,
+    RequestBodyTooLarge,
+    ResponseBodyTooLarge,
+}
+impl SizeLimitViolation {
+    pub fn get_status(&self) -> StatusCode {
+        match self {
+            SizeLimitViolation::HeaderTooLarge | SizeLimitViolation::TooManyHeaders => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            SizeLimitViolation::UriTooLong => StatusCode::URI_TOO_LONG,
+            SizeLimitViolation::RequestBodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            SizeLimitViolation::ResponseBodyTooLarge => StatusCode::BAD_GATEWAY,
+        }
+    }
+    pub fn get_reason(&self) -> &'static str {
+        match self {
+            SizeLimitViolation::HeaderTooLarge => "header_too_large",
+            SizeLimitViolation::TooManyHeaders => "too_many_headers",
+            SizeLimitViolation::UriTooLong => "uri_too_long",
+            SizeLimitViolation::RequestBodyTooLarge => "request_body_too_large",
+            SizeLimitViolation::ResponseBodyTooLarge => "response_body_too_large",
+        }
+    }
+}
+impl fmt::Display for SizeLimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get_reason())
+    }
+}